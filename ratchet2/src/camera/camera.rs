@@ -1,6 +1,6 @@
 use bevy::prelude::*;
 
-use crate::{player::{CameraTarget, GroundedHeight}, player_input::{get_camera_angle, DoubleJump, Jump, Longjump, SideflipL, SideflipR}};
+use crate::{player::{CameraTarget, GravityDirection, GroundedHeight, PlayerIntent}, player_input::get_camera_angle, player_state::PlayerState};
 
 pub struct CameraPlugin;
 impl Plugin for CameraPlugin {
@@ -58,28 +58,18 @@ const CAMERA_HEIGHT_SPEED: f32 = 12.;
 
 fn camera_rotate(
     mut camera: Query<(&Transform, &mut CameraIdentifier)>,
-    target: Query<&Transform, (With<CameraTarget>, Without<CameraIdentifier>)>,
-    gamepads: Res<Gamepads>,
-    axes: Res<Axis<GamepadAxis>>,
-    time: Res<Time>
+    target: Query<(&Transform, &GravityDirection), (With<CameraTarget>, Without<CameraIdentifier>)>,
+    intent: Query<&PlayerIntent, With<CameraTarget>>,
 ) {
     let Ok((cam_transform, mut camera_angle)) = camera.get_single_mut() else {return;};
-    let Ok(target_transform) = target.get_single() else {return;};
+    let Ok((target_transform, gravity_direction)) = target.get_single() else {return;};
+    let Ok(intent) = intent.get_single() else {return;};
     
-    for gamepad in gamepads.iter() {
-        let axis_lx = GamepadAxis {
-            gamepad,
-            axis_type: GamepadAxisType::RightStickX
-        };
-
-        if let Some(x) = axes.get(axis_lx) {
-            if x > 0.2 || x < -0.2 {
-                camera_angle.0 += x * time.delta_seconds() * 2.;
-            }
-            else {
-                camera_angle.0 = get_camera_angle(cam_transform, target_transform);
-            }
-        }
+    if intent.look.x != 0. {
+        camera_angle.0 += intent.look.x * 2.;
+    }
+    else {
+        camera_angle.0 = get_camera_angle(cam_transform, target_transform, gravity_direction);
     }
 
     if camera_angle.0 > 6.28319 {
@@ -95,50 +85,33 @@ fn camera_rotate(
 fn camera_height (
     mut camera: Query<(&mut CameraHeight, &mut CameraRealHeight)>,
     target: Query<(
-        Has<DoubleJump>, 
-        Has<Jump>, 
-        Has<SideflipL>, 
-        Has<SideflipR>, 
-        Has<Longjump>, 
+        &PlayerState,
         &Transform,
-        &GroundedHeight
+        &GroundedHeight,
+        &GravityDirection
     ), (With<CameraTarget>, Without<CameraRealHeight>)>,
-    gamepads: Res<Gamepads>,
-    axes: Res<Axis<GamepadAxis>>,
+    intent: Query<&PlayerIntent, With<CameraTarget>>,
     time: Res<Time>,
 ) {
     let Ok((mut camera_height, mut real_camera_height)) = camera.get_single_mut() else {return;};
-    let Ok((target_doublejump, target_jump, target_sideflip_l, target_sideflip_r, target_longjump, player_transform, grounded_height)) = target.get_single() else {return;};
-
-    for gamepad in gamepads.iter() {
-        let axis_ly = GamepadAxis {
-            gamepad,
-            axis_type: GamepadAxisType::RightStickY
-        };
-
-        if let Some(y) = axes.get(axis_ly) {
-
-            // if the stick is neutral it gradually puts back the camera to 0  
-            if y < 0.2 && y > -0.2 {
-                camera_height.0 = lerp(camera_height.0, 0., 3., &time);
-            }
-            else {
-                if camera_height.0 < 4. && camera_height.0 > -1.5 {
-                    camera_height.0 += y * time.delta_seconds() * 5.;
-                }
-            }
-        }
+    let Ok((target_state, player_transform, grounded_height, gravity_direction)) = target.get_single() else {return;};
+    let Ok(intent) = intent.get_single() else {return;};
 
+    // if the player isn't looking around it gradually puts back the camera to 0  
+    if intent.look.y == 0. {
+        camera_height.0 = lerp(camera_height.0, 0., 3., &time);
+    }
+    else {
+        if camera_height.0 < 4. && camera_height.0 > -1.5 {
+            camera_height.0 += intent.look.y * 5.;
+        }
     }
 
-    if !target_doublejump
-    && !target_jump
-    && !target_sideflip_l
-    && !target_sideflip_r
-    && !target_longjump {
+    // the heights are measured along the player's gravity
+    if !follows_grounded_height(*target_state) {
         real_camera_height.0 = lerp(
             real_camera_height.0,
-                player_transform.translation.y + 1. + camera_height.0, 
+                gravity_direction.height(player_transform.translation) + 1. + camera_height.0, 
                 CAMERA_HEIGHT_SPEED,
                 &time
         );
@@ -156,6 +129,15 @@ fn camera_height (
 }
 
 
+// during jumps the camera stays at the height the player jumped from
+fn follows_grounded_height(state: PlayerState) -> bool {
+    matches!(
+        state,
+        PlayerState::Jump | PlayerState::DoubleJump | PlayerState::SideflipL | PlayerState::SideflipR | PlayerState::Longjump
+    )
+}
+
+
 fn lerp(
     mut camera_height: f32,
     target_value: f32,
@@ -185,73 +167,53 @@ fn lerp(
 
 fn camera_position (
     mut camera: Query<(&mut Transform, &mut CameraRealHeight, &CameraIdentifier)>,
-    target: Query<&Transform , (With<CameraTarget>, Without<CameraRealHeight>)>,
+    target: Query<(&Transform, &GravityDirection), (With<CameraTarget>, Without<CameraRealHeight>)>,
 
 
-    gamepads: Res<Gamepads>,
-    axes: Res<Axis<GamepadAxis>>,
+    intent: Query<&PlayerIntent, With<CameraTarget>>,
 ) {
 
     let Ok((mut camera_transform, camera_height, camera_angle)) = camera.get_single_mut() else {return;};
-    let Ok(target_transform) = target.get_single() else {return;};
-
-    for gamepad in gamepads.iter() {
-
-        let camera_y =  camera_height.0; 
-
-        let mut position = Vec2::ZERO;
-
-        let axis_lx = GamepadAxis {
-            gamepad,
-            axis_type: GamepadAxisType::RightStickX
-        };
-
-        if let Some(x) = axes.get(axis_lx) {
+    let Ok((target_transform, gravity_direction)) = target.get_single() else {return;};
+    let Ok(intent) = intent.get_single() else {return;};
 
-            if x > 0.2 || x < -0.2 {
-                // if isn't neutral calculates the camera position through goniometry
-                position = Vec2::new(
-                    target_transform.translation.x + CAMERA_DISTANCE * f32::cos(camera_angle.0), 
-                    target_transform.translation.z + CAMERA_DISTANCE * f32::sin(camera_angle.0)
-                );
+    // the camera orbits the player as if it were standing upright, then it's turned back to its gravity
+    let frame = gravity_direction.frame();
+    let target = frame.inverse() * target_transform.translation;
 
-            }
-            else if camera_transform.translation.distance(target_transform.translation) != CAMERA_DISTANCE {
-                // if the stick is neutral it just checks the distance between the camera and the player
-                // if the camera and the player are too far apart or too close it adjusts the camera position
-                position = Vec2::new(
-                    target_transform.translation.x + (camera_transform.back().normalize().x * CAMERA_DISTANCE), 
-                    target_transform.translation.z + (camera_transform.back().normalize().z * CAMERA_DISTANCE)
-                );
+    let camera_y =  camera_height.0; 
 
-            }
+    let mut position = Vec2::ZERO;
 
-
-            camera_transform.translation = camera_transform.translation.lerp(
-                Vec3::new(
-                    position.x, 
-                    camera_y, 
-                    position.y
-                ),
-                0.07
-            );
-
-        }
-
-        
-
-        println!("height: {}", camera_y);
-        println!("transform: {}", camera_transform.translation.y);
+    if intent.look.x != 0. {
+        // if isn't neutral calculates the camera position through goniometry
+        position = Vec2::new(
+            target.x + CAMERA_DISTANCE * f32::cos(camera_angle.0), 
+            target.z + CAMERA_DISTANCE * f32::sin(camera_angle.0)
+        );
 
     }
+    else if camera_transform.translation.distance(target_transform.translation) != CAMERA_DISTANCE {
+        // if the stick is neutral it just checks the distance between the camera and the player
+        // if the camera and the player are too far apart or too close it adjusts the camera position
+        let back = (frame.inverse() * camera_transform.back()).normalize();
+
+        position = Vec2::new(
+            target.x + (back.x * CAMERA_DISTANCE), 
+            target.z + (back.z * CAMERA_DISTANCE)
+        );
 
+    }
 
 
-    
-    
-            
-    
-
+    camera_transform.translation = camera_transform.translation.lerp(
+        frame * Vec3::new(
+            position.x, 
+            camera_y, 
+            position.y
+        ),
+        0.07
+    );
 }
 
 
@@ -260,27 +222,22 @@ fn camera_rotation(
     target: Query<(
         &Transform,
         &GroundedHeight,
-        Has<DoubleJump>, 
-        Has<Jump>, 
-        Has<SideflipL>, 
-        Has<SideflipR>, 
-        Has<Longjump>
+        &PlayerState,
+        &GravityDirection
     ), (With<CameraTarget>, Without<CameraIdentifier>)>,
 
 ) {
-    let Ok((target_transform, grounded_height, target_doublejump, target_jump, target_sideflip_l, target_sideflip_r, target_longjump)) = target.get_single() else {return;};
+    let Ok((target_transform, grounded_height, target_state, gravity_direction)) = target.get_single() else {return;};
+
+    // the camera rolls with the player on walkways and around planets
+    let up = gravity_direction.up();
     let Ok(mut camera_transform) = camera.get_single_mut() else {return;};
    
-    if !target_doublejump
-    && !target_jump
-    && !target_sideflip_l
-    && !target_sideflip_r
-    && !target_longjump {
-        let target_rotation = camera_transform.looking_at(Vec3::new(
-            target_transform.translation.x, 
-            target_transform.translation.y + 0.5,
-            target_transform.translation.z
-        ), Vec3::Y).rotation;
+    if !follows_grounded_height(*target_state) {
+        let target_rotation = camera_transform.looking_at(
+            target_transform.translation + up * 0.5,
+            up
+        ).rotation;
     
         camera_transform.rotation = camera_transform.rotation.slerp(
             target_rotation,
@@ -288,11 +245,13 @@ fn camera_rotation(
         );
     }
     else {
-        let target_rotation = camera_transform.looking_at(Vec3::new(
-            target_transform.translation.x, 
-            grounded_height.0 + 0.5,
-            target_transform.translation.z
-        ), Vec3::Y).rotation;
+        // the player's own height along its gravity swapped for the grounded one
+        let height = gravity_direction.height(target_transform.translation);
+
+        let target_rotation = camera_transform.looking_at(
+            target_transform.translation + up * (grounded_height.0 + 0.5 - height),
+            up
+        ).rotation;
     
         camera_transform.rotation = camera_transform.rotation.slerp(
             target_rotation,
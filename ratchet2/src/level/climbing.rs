@@ -0,0 +1,57 @@
+use bevy::prelude::*;
+
+use crate::level::{LevelNodes, LevelVolume};
+
+pub struct ClimbingPlugin;
+
+impl Plugin for ClimbingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, spawn_climbables);
+    }
+}
+
+// a level mesh the player can climb anywhere on, like a fence or a vine wall
+#[derive(Component)]
+pub struct Climbable;
+
+// a box the player climbs in, facing the side it's entered from
+#[derive(Component)]
+pub struct Ladder {
+    pub bounds: LevelVolume
+}
+
+impl Ladder {
+    // the way the front of the ladder faces, the player climbs looking the other way
+    pub fn normal(&self, up: Vec3) -> Vec3 {
+        let normal = self.bounds.rotation * Vec3::Z;
+
+        (normal - up * normal.dot(up)).normalize_or_zero()
+    }
+}
+
+
+// climbable surfaces are authored in the level collisions as meshes named "climb...",
+// the collider can end up on any of their children so all of them are tagged,
+// ladders are cube empties named "ladder..." scaled to the size of the ladder with their front along z
+fn spawn_climbables(
+    mut level_nodes: LevelNodes,
+    children: Query<&Children>,
+    mut commands: Commands,
+) {
+    for (node, name) in level_nodes.spawned() {
+        if name.starts_with("climb") {
+            commands.entity(node).insert(Climbable);
+
+            for child in children.iter_descendants(node) {
+                commands.entity(child).insert(Climbable);
+            }
+        }
+        else if name.starts_with("ladder") {
+            let Some(transform) = level_nodes.global_transform(node) else {continue;};
+
+            commands.entity(node).insert(Ladder {
+                bounds: LevelVolume::from_transform(&transform)
+            });
+        }
+    }
+}
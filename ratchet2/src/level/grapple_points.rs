@@ -0,0 +1,44 @@
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::level::LevelNodes;
+
+pub struct GrapplePointPlugin;
+
+impl Plugin for GrapplePointPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, spawn_grapple_points);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum GrappleMode {
+    // reels the player in up to the point
+    Pull,
+    // the player hangs from the point on a rope and swings
+    Swing
+}
+
+// something the swingshot can latch onto
+#[derive(Component)]
+pub struct GrapplePoint(pub GrappleMode);
+
+
+// grapple points are authored in the level collisions as empties named "grapple...",
+// the ones with "swing" in the name are swung from, the others pull the player in
+fn spawn_grapple_points(
+    mut level_nodes: LevelNodes,
+    mut commands: Commands,
+) {
+    for (node, name) in level_nodes.spawned() {
+        if !name.starts_with("grapple") {continue;}
+
+        let mode = if name.contains("swing") {GrappleMode::Swing} else {GrappleMode::Pull};
+
+        // the rope is a joint, it needs a body on both ends
+        commands.entity(node).insert((
+            GrapplePoint(mode),
+            RigidBody::Static
+        ));
+    }
+}
@@ -0,0 +1,63 @@
+use bevy::prelude::*;
+
+use crate::level::{LevelNodes, LevelVolume};
+
+pub struct GravityVolumePlugin;
+
+impl Plugin for GravityVolumePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, spawn_gravity_volumes);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GravityField {
+    // pulls the same way everywhere in the box, like a magnetic walkway
+    Planar(Vec3),
+    // pulls towards the center of a sphere, like a small planet
+    Radial
+}
+
+// a part of the level where gravity pulls the player some other way than down
+#[derive(Component)]
+pub struct GravityVolume {
+    pub bounds: LevelVolume,
+    pub field: GravityField
+}
+
+impl GravityVolume {
+    // which way gravity pulls at this point, if the point is inside the volume
+    pub fn down_at(&self, point: Vec3) -> Option<Vec3> {
+        match self.field {
+            GravityField::Planar(down) => self.bounds.contains(point).then_some(down),
+            GravityField::Radial => {
+                if point.distance(self.bounds.center) > self.bounds.half_extents.max_element() {return None;}
+
+                (self.bounds.center - point).try_normalize()
+            }
+        }
+    }
+}
+
+
+// gravity volumes are authored in the level collisions as empties named "gravity...",
+// cubes scaled to the size of the walkway pulling along their down axis,
+// or spheres with "planet" in the name pulling towards their center
+fn spawn_gravity_volumes(
+    mut level_nodes: LevelNodes,
+    mut commands: Commands,
+) {
+    for (node, name) in level_nodes.spawned() {
+        if !name.starts_with("gravity") {continue;}
+
+        let Some(transform) = level_nodes.global_transform(node) else {continue;};
+        let bounds = LevelVolume::from_transform(&transform);
+
+        let field = if name.contains("planet") {GravityField::Radial} else {GravityField::Planar(bounds.rotation * Vec3::NEG_Y)};
+
+        commands.entity(node).insert(GravityVolume {
+            bounds,
+            field
+        });
+    }
+}
@@ -1,11 +1,14 @@
-use bevy::{audio::{PlaybackMode, Volume}, pbr::CascadeShadowConfigBuilder, prelude::*};
+use bevy::{asset::ron, audio::{PlaybackMode, Volume}, ecs::system::SystemParam, gltf::{Gltf, GltfExtras}, pbr::CascadeShadowConfigBuilder, prelude::*, scene::SceneInstanceReady, transform::helper::TransformHelper, utils::HashMap};
 use bevy_xpbd_3d::{components::RigidBody, plugins::collision::{AsyncSceneCollider, ComputedCollider}};
 
+use crate::{climbing::ClimbingPlugin, grapple_points::GrapplePointPlugin, gravity_volumes::GravityVolumePlugin, platforms::PlatformPlugin, rails::RailPlugin, surfaces::SurfacePlugin, water::WaterPlugin, wind::WindPlugin};
+
 pub struct LevelPlugin;
 
 impl Plugin for LevelPlugin {
     fn build(&self, app: &mut App) {
         app
+            .add_plugins((PlatformPlugin, RailPlugin, GrapplePointPlugin, WindPlugin, WaterPlugin, ClimbingPlugin, GravityVolumePlugin, SurfacePlugin))
             .add_systems(Startup, (
                 spawn_terrain, 
                 level_music
@@ -16,6 +19,89 @@ impl Plugin for LevelPlugin {
 #[derive(Component)]
 pub struct LevelCollider;
 
+// the whole collisions file, its materials tell what the surfaces are made of
+#[derive(Resource)]
+pub struct LevelCollisions(pub Handle<Gltf>);
+
+// a box authored in the level collisions as a cube empty scaled to its size
+#[derive(Clone, Copy)]
+pub struct LevelVolume {
+    pub center: Vec3,
+    pub rotation: Quat,
+    pub half_extents: Vec3
+}
+
+impl LevelVolume {
+    pub fn from_transform(transform: &GlobalTransform) -> Self {
+        let (scale, rotation, translation) = transform.to_scale_rotation_translation();
+
+        Self {
+            center: translation,
+            rotation,
+            half_extents: scale.abs()
+        }
+    }
+
+    pub fn contains(&self, point: Vec3) -> bool {
+        let local = self.rotation.inverse() * (point - self.center);
+
+        local.abs().cmple(self.half_extents).all()
+    }
+}
+
+// the level features are authored in the level collisions as named nodes,
+// they're read once the collisions scene has spawned
+#[derive(SystemParam)]
+pub struct LevelNodes<'w, 's> {
+    scenes_ready: EventReader<'w, 's, SceneInstanceReady>,
+    level_collider: Query<'w, 's, (), With<LevelCollider>>,
+    names: Query<'w, 's, &'static Name>,
+    children: Query<'w, 's, &'static Children>,
+    transform_helper: TransformHelper<'w, 's>
+}
+
+impl LevelNodes<'_, '_> {
+    // every named node of the collisions scenes that spawned since the last time, with the name in lowercase
+    pub fn spawned(&mut self) -> Vec<(Entity, String)> {
+        let scenes: Vec<Entity> = self.scenes_ready.read()
+            .map(|scene| scene.parent)
+            .filter(|scene| self.level_collider.contains(*scene))
+            .collect();
+
+        scenes.into_iter()
+            .flat_map(|scene| self.children.iter_descendants(scene))
+            .filter_map(|node| self.names.get(node).ok().map(|name| (node, name.as_str().to_lowercase())))
+            .collect()
+    }
+
+    // the scene was just spawned, so the transforms haven't been propagated yet
+    pub fn global_transform(&self, node: Entity) -> Option<GlobalTransform> {
+        self.transform_helper.compute_global_transform(node).ok()
+    }
+}
+
+// whether a level node is named after a feature, either exactly or followed by blender's ".001"
+// copy numbers or an "_" and a description, so a "railing" isn't taken for a "rail"
+pub fn is_feature(name: &str, feature: &str) -> bool {
+    name.strip_prefix(feature)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(['.', '_']))
+}
+
+// a custom property of a level node, the extras are json and a flat json object reads as a ron map
+pub fn level_property(extras: &GltfExtras, property: &str) -> Option<ron::Value> {
+    let mut properties = ron::de::from_str::<HashMap<String, ron::Value>>(&extras.value).ok()?;
+
+    properties.remove(property)
+}
+
+// blender writes whole numbers without a decimal point, so integers count too
+pub fn number_property(extras: &GltfExtras, property: &str) -> Option<f32> {
+    match level_property(extras, property)? {
+        ron::Value::Number(number) => Some(number.into_f64() as f32),
+        _ => None
+    }
+}
+
 fn spawn_terrain(
     mut commands: Commands,
     assets: Res<AssetServer>,
@@ -52,6 +138,10 @@ fn spawn_terrain(
 
 
     // [TERRAIN COLLISION]
+    commands.insert_resource(LevelCollisions(assets.load("./insomniac_museum_collisions.glb")));
+    //commands.insert_resource(LevelCollisions(assets.load("./metropolis_collisions.glb")));
+    //commands.insert_resource(LevelCollisions(assets.load("./veldin_collisions.glb")));
+
     commands.spawn((
         SceneBundle {
             scene: assets.load("./insomniac_museum_collisions.glb#Scene0"),
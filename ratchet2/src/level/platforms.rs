@@ -0,0 +1,109 @@
+use bevy::{gltf::GltfExtras, prelude::*};
+use bevy_xpbd_3d::prelude::*;
+
+use crate::{level::{number_property, LevelNodes, LevelVolume}, player_controls::playing};
+
+pub struct PlatformPlugin;
+
+impl Plugin for PlatformPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, spawn_platforms)
+            .add_systems(FixedUpdate, follow_path.run_if(playing));
+    }
+}
+
+// units per second when the level doesn't say
+const DEFAULT_PLATFORM_SPEED: f32 = 3.;
+
+// a kinematic platform that loops through its waypoints, going from the last one straight back to the first
+#[derive(Component)]
+pub struct PlatformPath {
+    // starts with where the platform was placed, so it's never empty
+    pub waypoints: Vec<Vec3>,
+    // units per second
+    pub speed: f32,
+    // the waypoint it's going to
+    pub next: usize
+}
+
+
+// sets the velocity of every platform towards its next waypoint,
+// the physics moves it so whatever stands on it can read the same velocity
+fn follow_path(
+    mut platforms: Query<(&mut PlatformPath, &Position, &mut LinearVelocity)>,
+    time: Res<Time>,
+) {
+    for (mut path, position, mut linear_velocity) in platforms.iter_mut() {
+        let mut to_next = path.waypoints[path.next] - position.0;
+
+        if to_next.length() <= path.speed * time.delta_seconds() {
+            path.next = (path.next + 1) % path.waypoints.len();
+            to_next = path.waypoints[path.next] - position.0;
+        }
+
+        linear_velocity.0 = to_next.normalize_or_zero() * path.speed;
+    }
+}
+
+
+// platforms are authored in the level collisions as cube empties named "platform...", scaled to the size of the platform,
+// their children are the waypoints it goes through in name order after its own position,
+// "speed" and "spin" custom properties set how fast it goes and how many radians a second it turns around its up axis
+fn spawn_platforms(
+    mut level_nodes: LevelNodes,
+    nodes: Query<(Option<&Children>, Option<&GltfExtras>)>,
+    names: Query<&Name>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (node, name) in level_nodes.spawned() {
+        if !name.starts_with("platform") {continue;}
+
+        let Ok((waypoint_nodes, extras)) = nodes.get(node) else {continue;};
+        let Some(transform) = level_nodes.global_transform(node) else {continue;};
+        let bounds = LevelVolume::from_transform(&transform);
+
+        let mut waypoint_nodes: Vec<(&Name, Entity)> = waypoint_nodes.into_iter()
+            .flatten()
+            .filter_map(|waypoint_node| names.get(*waypoint_node).ok().map(|name| (name, *waypoint_node)))
+            .collect();
+        waypoint_nodes.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+
+        let mut waypoints = vec![bounds.center];
+        waypoints.extend(waypoint_nodes.iter()
+            .filter_map(|(_, waypoint_node)| level_nodes.global_transform(*waypoint_node))
+            .map(|transform| transform.translation())
+        );
+
+        let speed = extras.and_then(|extras| number_property(extras, "speed")).unwrap_or(DEFAULT_PLATFORM_SPEED);
+        let spin = extras.and_then(|extras| number_property(extras, "spin")).unwrap_or(0.);
+
+        let size = bounds.half_extents * 2.;
+
+        // the collisions are hidden and the platform has to move on its own, so it's spawned apart from them
+        let mut platform = commands.spawn((
+            PbrBundle {
+                mesh: meshes.add(Cuboid::from_size(size)),
+                material: materials.add(StandardMaterial {
+                    base_color: Color::rgb(0.55, 0.6, 0.7),
+                    ..default()
+                }),
+                transform: Transform::from_translation(bounds.center).with_rotation(bounds.rotation),
+                ..default()
+            },
+            RigidBody::Kinematic,
+            Collider::cuboid(size.x, size.y, size.z),
+            AngularVelocity(bounds.rotation * Vec3::Y * spin),
+        ));
+
+        if waypoints.len() > 1 {
+            platform.insert(PlatformPath {
+                waypoints,
+                speed,
+                next: 1
+            });
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use bevy::prelude::*;
+
+use crate::level::{is_feature, LevelNodes};
+
+pub struct RailPlugin;
+
+impl Plugin for RailPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, spawn_rails);
+    }
+}
+
+// how many straight pieces every piece of the spline is split into
+const RAIL_SUBDIVISIONS: usize = 8;
+
+// a grind rail, the spline is split into short straight pieces
+// so the player can move along it at a constant speed
+#[derive(Component)]
+pub struct Rail {
+    pub points: Vec<Vec3>,
+    // how far along the rail every point is
+    pub distances: Vec<f32>
+}
+
+impl Rail {
+    // a spline going through every control point, at least two are needed
+    pub fn from_control_points(control_points: &[Vec3]) -> Option<Self> {
+        if control_points.len() < 2 {return None;}
+
+        // a catmull-rom spline doesn't reach its first and last points, repeating them makes it
+        let mut spline_points = vec![control_points[0]];
+        spline_points.extend_from_slice(control_points);
+        spline_points.push(control_points[control_points.len() - 1]);
+
+        let curve = CubicCardinalSpline::new_catmull_rom(spline_points).to_curve();
+        let points: Vec<Vec3> = curve.iter_positions((control_points.len() - 1) * RAIL_SUBDIVISIONS).collect();
+
+        let mut distances = vec![0.];
+        for piece in points.windows(2) {
+            distances.push(distances[distances.len() - 1] + piece[0].distance(piece[1]));
+        }
+
+        Some(Self {
+            points,
+            distances
+        })
+    }
+
+    pub fn length(&self) -> f32 {
+        self.distances[self.distances.len() - 1]
+    }
+
+    // the position and the direction of the rail this far along it
+    pub fn sample(&self, distance: f32) -> (Vec3, Vec3) {
+        let distance = distance.clamp(0., self.length());
+        let piece = self.distances.partition_point(|d| *d <= distance).clamp(1, self.points.len() - 1) - 1;
+
+        let (start, end) = (self.points[piece], self.points[piece + 1]);
+        let piece_length = self.distances[piece + 1] - self.distances[piece];
+        let t = if piece_length > 0. {(distance - self.distances[piece]) / piece_length} else {0.};
+
+        (start.lerp(end, t), (end - start).normalize_or_zero())
+    }
+
+    // how far along the rail the closest point to this one is, and where it is
+    pub fn closest(&self, point: Vec3) -> (f32, Vec3) {
+        let mut closest = (0., self.points[0]);
+        let mut closest_distance = f32::MAX;
+
+        for (piece, ends) in self.points.windows(2).enumerate() {
+            let along = ends[1] - ends[0];
+            let t = if along.length_squared() > 0. {((point - ends[0]).dot(along) / along.length_squared()).clamp(0., 1.)} else {0.};
+            let on_rail = ends[0] + along * t;
+
+            if on_rail.distance(point) < closest_distance {
+                closest_distance = on_rail.distance(point);
+                closest = (self.distances[piece] + along.length() * t, on_rail);
+            }
+        }
+
+        closest
+    }
+}
+
+
+// rails are authored in the level collisions as empties named "rail", "rail.001" or "rail_...",
+// their children are the control points in name order
+fn spawn_rails(
+    mut level_nodes: LevelNodes,
+    nodes: Query<(&Name, &Children)>,
+    names: Query<&Name>,
+    mut commands: Commands,
+) {
+    for (node, name) in level_nodes.spawned() {
+        if !is_feature(&name, "rail") {continue;}
+
+        let Ok((name, control_nodes)) = nodes.get(node) else {continue;};
+
+        let mut control_nodes: Vec<(&Name, Entity)> = control_nodes.iter()
+            .filter_map(|control_node| names.get(*control_node).ok().map(|name| (name, *control_node)))
+            .collect();
+        control_nodes.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+
+        let control_points: Vec<Vec3> = control_nodes.iter()
+            .filter_map(|(_, control_node)| level_nodes.global_transform(*control_node))
+            .map(|transform| transform.translation())
+            .collect();
+
+        let Some(rail) = Rail::from_control_points(&control_points) else {
+            warn!("rail {} needs at least two control points", name);
+            continue;
+        };
+
+        commands.spawn((
+            Name::new(name.to_string()),
+            rail
+        ));
+    }
+}
@@ -0,0 +1,106 @@
+use bevy::{asset::ron, gltf::{Gltf, GltfExtras}, prelude::*, scene::SceneInstanceReady};
+
+use crate::level::{level_property, LevelCollider, LevelCollisions};
+
+pub struct SurfacePlugin;
+
+impl Plugin for SurfacePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, tag_surfaces);
+    }
+}
+
+// what the ground is made of, it changes the grip, the footsteps and the dust
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Surface {
+    #[default]
+    Default,
+    Ice,
+    Mud,
+    Metal,
+    Grass
+}
+
+impl Surface {
+    // the surface anywhere in the name, so a material like "floor_metal.001" counts too
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+
+        [("ice", Surface::Ice), ("mud", Surface::Mud), ("metal", Surface::Metal), ("grass", Surface::Grass)]
+            .into_iter()
+            .find(|(surface_name, _)| name.contains(surface_name))
+            .map(|(_, surface)| surface)
+    }
+
+    pub fn footstep_sound(self) -> &'static str {
+        match self {
+            Surface::Default => "footstep.ogg",
+            Surface::Ice => "footstep_ice.ogg",
+            Surface::Mud => "footstep_mud.ogg",
+            Surface::Metal => "footstep_metal.ogg",
+            Surface::Grass => "footstep_grass.ogg"
+        }
+    }
+
+    pub fn dust_color(self) -> Color {
+        match self {
+            Surface::Default => Color::rgb(0.75, 0.68, 0.55),
+            Surface::Ice => Color::rgb(0.85, 0.93, 1.),
+            Surface::Mud => Color::rgb(0.35, 0.25, 0.15),
+            Surface::Metal => Color::rgb(1., 0.85, 0.45),
+            Surface::Grass => Color::rgb(0.45, 0.7, 0.3)
+        }
+    }
+}
+
+// a level mesh the player feels the surface of
+#[derive(Component)]
+pub struct SurfaceMaterial(pub Surface);
+
+
+// the surface of a level mesh comes from a "surface" custom property on the mesh or on its object,
+// or else from the name of its material
+fn tag_surfaces(
+    mut scenes_ready: EventReader<SceneInstanceReady>,
+    level_collider: Query<(), With<LevelCollider>>,
+    meshes: Query<(&Handle<StandardMaterial>, Option<&GltfExtras>, Option<&Parent>)>,
+    extras: Query<&GltfExtras>,
+    children: Query<&Children>,
+    level_collisions: Res<LevelCollisions>,
+    gltfs: Res<Assets<Gltf>>,
+    mut commands: Commands,
+) {
+    for scene in scenes_ready.read() {
+        if !level_collider.contains(scene.parent) {continue;}
+
+        // the scene is spawned out of the same file, so the file is loaded by now
+        let Some(gltf) = gltfs.get(&level_collisions.0) else {
+            warn!("the level collisions aren't loaded, the surfaces can't be read");
+            continue;
+        };
+
+        for node in children.iter_descendants(scene.parent) {
+            let Ok((material, mesh_extras, parent)) = meshes.get(node) else {continue;};
+
+            let object_extras = parent.and_then(|parent| extras.get(parent.get()).ok());
+
+            let surface = mesh_extras.and_then(surface_property)
+                .or_else(|| object_extras.and_then(surface_property))
+                .or_else(|| gltf.named_materials.iter()
+                    .find(|(_, named_material)| *named_material == material)
+                    .and_then(|(name, _)| Surface::from_name(name))
+                );
+
+            if let Some(surface) = surface {
+                commands.entity(node).insert(SurfaceMaterial(surface));
+            }
+        }
+    }
+}
+
+fn surface_property(extras: &GltfExtras) -> Option<Surface> {
+    match level_property(extras, "surface")? {
+        ron::Value::String(name) => Surface::from_name(&name),
+        _ => None
+    }
+}
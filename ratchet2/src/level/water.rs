@@ -0,0 +1,49 @@
+use bevy::prelude::*;
+
+use crate::level::LevelNodes;
+
+pub struct WaterPlugin;
+
+impl Plugin for WaterPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, spawn_water_volumes);
+    }
+}
+
+// a box of water, the top of it is the surface
+#[derive(Component)]
+pub struct WaterVolume {
+    pub min: Vec3,
+    pub max: Vec3
+}
+
+impl WaterVolume {
+    // the height of the surface if the point is above the bottom of the water, however high
+    pub fn surface_at(&self, point: Vec3) -> Option<f32> {
+        let inside = point.x >= self.min.x && point.x <= self.max.x
+            && point.z >= self.min.z && point.z <= self.max.z
+            && point.y >= self.min.y;
+
+        inside.then_some(self.max.y)
+    }
+}
+
+
+// water volumes are authored in the level collisions as cube empties named "water...",
+// scaled to the size of the volume; water is always level so their rotation is ignored
+fn spawn_water_volumes(
+    mut level_nodes: LevelNodes,
+    mut commands: Commands,
+) {
+    for (node, name) in level_nodes.spawned() {
+        if !name.starts_with("water") {continue;}
+
+        let Some(transform) = level_nodes.global_transform(node) else {continue;};
+        let (scale, _, translation) = transform.to_scale_rotation_translation();
+
+        commands.entity(node).insert(WaterVolume {
+            min: translation - scale.abs(),
+            max: translation + scale.abs()
+        });
+    }
+}
@@ -0,0 +1,48 @@
+use bevy::{gltf::GltfExtras, prelude::*};
+
+use crate::level::{is_feature, number_property, LevelNodes, LevelVolume};
+
+pub struct WindPlugin;
+
+impl Plugin for WindPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, spawn_wind_volumes);
+    }
+}
+
+// how hard a volume pushes when the level doesn't say, enough to beat the player gravity
+const DEFAULT_WIND_STRENGTH: f32 = 40.;
+
+// a box that pushes the player while gliding, pointing up it's an updraft
+#[derive(Component)]
+pub struct WindVolume {
+    pub bounds: LevelVolume,
+    // acceleration in units per second squared
+    pub force: Vec3
+}
+
+
+// wind volumes are authored in the level collisions as cube empties named "wind", "wind.001" or "wind_...",
+// scaled to the size of the volume and blowing along their up axis,
+// a "strength" custom property changes how hard
+fn spawn_wind_volumes(
+    mut level_nodes: LevelNodes,
+    extras: Query<&GltfExtras>,
+    mut commands: Commands,
+) {
+    for (node, name) in level_nodes.spawned() {
+        if !is_feature(&name, "wind") {continue;}
+
+        let Some(transform) = level_nodes.global_transform(node) else {continue;};
+        let bounds = LevelVolume::from_transform(&transform);
+
+        let strength = extras.get(node).ok()
+            .and_then(|extras| number_property(extras, "strength"))
+            .unwrap_or(DEFAULT_WIND_STRENGTH);
+
+        commands.entity(node).insert(WindVolume {
+            bounds,
+            force: bounds.rotation * Vec3::Y * strength
+        });
+    }
+}
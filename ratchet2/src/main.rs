@@ -1,5 +1,11 @@
+use std::process::ExitCode;
+use std::time::Duration;
+
+use bevy::app::ScheduleRunnerPlugin;
 use bevy::prelude::*;
-use bevy::window::PrimaryWindow;
+use bevy::render::{settings::WgpuSettings, RenderPlugin};
+use bevy::window::{ExitCondition, PrimaryWindow};
+use bevy::winit::WinitPlugin;
 use bevy_editor_pls::EditorPlugin;
 use bevy_xpbd_3d::plugins::PhysicsPlugins;
 
@@ -11,6 +17,30 @@ use crate::camera::CameraPlugin;
 mod level;
 use crate::level::LevelPlugin;
 
+#[path = "./level/platforms.rs"]
+mod platforms;
+
+#[path = "./level/rails.rs"]
+mod rails;
+
+#[path = "./level/grapple_points.rs"]
+mod grapple_points;
+
+#[path = "./level/wind.rs"]
+mod wind;
+
+#[path = "./level/water.rs"]
+mod water;
+
+#[path = "./level/climbing.rs"]
+mod climbing;
+
+#[path = "./level/gravity_volumes.rs"]
+mod gravity_volumes;
+
+#[path = "./level/surfaces.rs"]
+mod surfaces;
+
 #[path = "./player/player_setup.rs"]
 mod player;
 use crate::player::PlayerPlugin;
@@ -18,6 +48,23 @@ use crate::player::PlayerPlugin;
 #[path = "./player/player_input.rs"]
 mod player_input;
 
+#[path = "./player/player_controls.rs"]
+mod player_controls;
+use crate::player_controls::PlayerControlsPlugin;
+
+#[path = "./player/input_map.rs"]
+mod input_map;
+
+#[path = "./player/input_replay.rs"]
+mod input_replay;
+use crate::input_replay::{InputReplay, InputReplayPlugin, ReplayOutcome};
+
+#[path = "./player/player_state.rs"]
+mod player_state;
+
+#[path = "./player/movement_tuning.rs"]
+mod movement_tuning;
+
 #[path = "./player/player_movement.rs"]
 mod player_movement;
 
@@ -27,19 +74,74 @@ mod player_animation;
 #[path = "./player/weapons.rs"]
 mod weapons;
 
-fn main() {
-    App::new()
+#[path = "./player/packs.rs"]
+mod packs;
+
+#[path = "./player/player_gravity.rs"]
+mod player_gravity;
+
+#[path = "./player/surface_effects.rs"]
+mod surface_effects;
+
+// how many times per second the character and the physics are updated
+const FIXED_HZ: f64 = 60.;
+
+fn main() -> ExitCode {
+    let input_replay = InputReplay::from_args();
+    let replay_outcome = ReplayOutcome::default();
+
+    let mut app = App::new();
+
+    if let InputReplay::Replay(_) = input_replay {
+        // replays run headless, the recorded input takes the place of the controls
+        app.add_plugins((
+            DefaultPlugins
+                .set(WindowPlugin {
+                    primary_window: None,
+                    exit_condition: ExitCondition::DontExit,
+                    close_when_requested: false
+                })
+                .set(RenderPlugin {
+                    render_creation: WgpuSettings {
+                        backends: None,
+                        ..default()
+                    }.into(),
+                    ..default()
+                })
+                .disable::<WinitPlugin>(),
+            ScheduleRunnerPlugin::run_loop(Duration::ZERO),
+        ));
+    }
+    else {
+        app
+            .add_plugins((
+                DefaultPlugins,
+                EditorPlugin::default(),
+                PlayerControlsPlugin,
+            ))
+            .add_systems(Startup, windows_settings);
+    }
+
+    app
         .add_plugins((
-            DefaultPlugins,
-            EditorPlugin::default(),
             CameraPlugin,
             LevelPlugin,
             PlayerPlugin,
-            PhysicsPlugins::default(),
+            // the physics steps right after the character systems so neither depends on the frame rate
+            PhysicsPlugins::new(FixedPostUpdate),
+            InputReplayPlugin(input_replay, replay_outcome.clone()),
         
         ))
-        .add_systems(Startup, windows_settings)
+        .insert_resource(Time::<Fixed>::from_hz(FIXED_HZ))
         .run();
+
+    // a script running the replay has to see it failed
+    if replay_outcome.diverged() {
+        ExitCode::FAILURE
+    }
+    else {
+        ExitCode::SUCCESS
+    }
 }
 
 
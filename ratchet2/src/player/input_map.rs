@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+
+use bevy::{
+    asset::{io::Reader, ron, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::BoxedFuture
+};
+use serde::Deserialize;
+
+pub struct InputMapPlugin;
+
+impl Plugin for InputMapPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_asset::<InputMap>()
+            .register_asset_loader(InputMapLoader)
+            .add_systems(PreStartup, load_input_map);
+    }
+}
+
+// maps every action name to the buttons, keys and sticks that trigger it
+// it's loaded from assets/controls.input.ron and reloaded when the file changes
+#[derive(Asset, TypePath, Deserialize)]
+pub struct InputMap {
+    pub buttons: HashMap<String, ButtonBinding>,
+    pub sticks: HashMap<String, StickBinding>
+}
+
+// everything that can press a button action
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct ButtonBinding {
+    pub gamepad: Vec<GamepadButtonType>,
+    pub keys: Vec<KeyCode>,
+    pub mouse: Vec<MouseButton>
+}
+
+// everything that can move a stick action
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct StickBinding {
+    pub gamepad: Option<GamepadStick>,
+    pub keys: Option<KeyStick>,
+    pub response: StickResponse
+}
+
+#[derive(Deserialize)]
+pub struct GamepadStick {
+    pub x: GamepadAxisType,
+    pub y: GamepadAxisType
+}
+
+// how the raw gamepad stick is cleaned up before the game reads it
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct StickResponse {
+    pub deadzone: Deadzone,
+    // below this the stick reads as centered
+    pub inner: f32,
+    // above this the stick reads as fully pushed
+    pub outer: f32,
+    pub curve: ResponseCurve
+}
+
+impl Default for StickResponse {
+    fn default() -> Self {
+        Self {
+            deadzone: Deadzone::Radial,
+            inner: 0.2,
+            outer: 0.95,
+            curve: ResponseCurve::Linear
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub enum Deadzone {
+    // the deadzone is a circle around the center, the direction is kept as is
+    Radial,
+    // every axis has its own deadzone, good for the camera
+    Axial
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub enum ResponseCurve {
+    Linear,
+    // values above 1 give finer control near the center
+    Power(f32)
+}
+
+impl StickResponse {
+    // always gives back a finite vector no longer than 1
+    pub fn apply(&self, raw: Vec2) -> Vec2 {
+        if !raw.is_finite() {
+            return Vec2::ZERO;
+        }
+
+        match self.deadzone {
+            Deadzone::Radial => {
+                let length = raw.length();
+
+                if length <= self.inner {
+                    return Vec2::ZERO;
+                }
+
+                raw / length * self.scale(length)
+            }
+            Deadzone::Axial => {
+                Vec2::new(
+                    raw.x.signum() * self.scale(raw.x.abs()),
+                    raw.y.signum() * self.scale(raw.y.abs())
+                ).clamp_length_max(1.)
+            }
+        }
+    }
+
+    // maps a distance from the center between the deadzones to 0..1 and bends it with the curve
+    fn scale(&self, value: f32) -> f32 {
+        if value <= self.inner {
+            return 0.;
+        }
+
+        let value = ((value - self.inner) / (self.outer - self.inner).max(f32::EPSILON)).clamp(0., 1.);
+
+        match self.curve {
+            ResponseCurve::Linear => value,
+            ResponseCurve::Power(exponent) if exponent.is_finite() && exponent > 0. => value.powf(exponent),
+            ResponseCurve::Power(_) => value
+        }
+    }
+}
+
+// four keys that act like a stick
+#[derive(Deserialize)]
+pub struct KeyStick {
+    pub up: KeyCode,
+    pub down: KeyCode,
+    pub left: KeyCode,
+    pub right: KeyCode
+}
+
+// handle to the input map used by the player
+#[derive(Resource)]
+pub struct InputMapHandle(pub Handle<InputMap>);
+
+
+#[derive(Default)]
+pub struct InputMapLoader;
+
+impl AssetLoader for InputMapLoader {
+    type Asset = InputMap;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a Self::Settings,
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+
+            Ok(ron::de::from_bytes::<InputMap>(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["input.ron"]
+    }
+}
+
+
+fn load_input_map(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+) {
+    commands.insert_resource(InputMapHandle(assets.load("controls.input.ron")));
+}
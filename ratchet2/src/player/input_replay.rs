@@ -0,0 +1,336 @@
+use std::{path::PathBuf, sync::{atomic::{AtomicBool, Ordering}, Arc}, time::Duration};
+
+use bevy::{app::AppExit, asset::ron, prelude::*, time::TimeUpdateStrategy};
+use bevy_xpbd_3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{camera::{CameraHeight, CameraIdentifier, CameraRealHeight}, level::LevelCollider, packs::PackUpgrades, player::{CharacterController, GravityDirection, InputBuffer, PlayerIntent, RailGrind}, player_input::Swing, player_state::PlayerState, weapons::Grapple};
+
+// replays advance the game by exactly one fixed step every frame
+const FRAME_TIME: f64 = 1. / 60.;
+
+// how far the replayed player can drift from the recorded one before it's reported
+const MAX_DEVIATION: f32 = 0.001;
+
+// whether the input is being recorded to a file or replayed from one,
+// picked from the --record <file> and --replay <file> command line arguments
+#[derive(Resource, Clone)]
+pub enum InputReplay {
+    Off,
+    Record(PathBuf),
+    Replay(PathBuf)
+}
+
+impl InputReplay {
+    pub fn from_args() -> Self {
+        let mut args = std::env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--record" => {
+                    if let Some(path) = args.next() {
+                        return InputReplay::Record(path.into());
+                    }
+                }
+                "--replay" => {
+                    if let Some(path) = args.next() {
+                        return InputReplay::Replay(path.into());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        InputReplay::Off
+    }
+}
+
+// whether the replay strayed from the recording, shared with main so it can be the exit code
+// once the app is done since the app itself can't return one
+#[derive(Resource, Clone, Default)]
+pub struct ReplayOutcome(Arc<AtomicBool>);
+
+impl ReplayOutcome {
+    pub fn diverged(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn set_diverged(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+pub struct InputReplayPlugin(pub InputReplay, pub ReplayOutcome);
+
+impl Plugin for InputReplayPlugin {
+    fn build(&self, app: &mut App) {
+        match &self.0 {
+            InputReplay::Off => {}
+            // the recording is played live at the real frame rate,
+            // every fixed step of the character and the physics is recorded
+            InputReplay::Record(_) => {
+                app
+                    .insert_resource(InputRecording::default())
+                    .add_systems(FixedLast, record_frame)
+                    .add_systems(Last, save_recording);
+            }
+            InputReplay::Replay(path) => {
+                let recording = std::fs::read_to_string(path)
+                    .map_err(|error| error.to_string())
+                    .and_then(|file| ron::from_str::<InputRecording>(&file).map_err(|error| error.to_string()))
+                    .unwrap_or_else(|error| panic!("couldn't read the recording {}: {}", path.display(), error));
+
+                app
+                    .insert_resource(recording)
+                    .insert_resource(self.1.clone())
+                    .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(FRAME_TIME)))
+                    .add_systems(FixedFirst, replay_intent.after(start_clock))
+                    .add_systems(FixedLast, compare_frame);
+            }
+        }
+
+        if !matches!(self.0, InputReplay::Off) {
+            app
+                .insert_resource(self.0.clone())
+                .insert_resource(ReplayClock::default())
+                .add_systems(FixedFirst, start_clock);
+        }
+    }
+}
+
+// the player and camera state when the recording started
+#[derive(Serialize, Deserialize, Default)]
+pub struct RecordedStart {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub velocity: Vec3,
+    pub camera_transform: Transform,
+    pub camera_angle: f32,
+    pub camera_height: f32,
+    pub camera_real_height: f32
+}
+
+// what the player wanted to do during a fixed step, where the camera it was steering by was
+// and where the player ended up
+#[derive(Serialize, Deserialize)]
+pub struct RecordedFrame {
+    pub intent: PlayerIntent,
+    pub camera_transform: Transform,
+    pub translation: Vec3
+}
+
+#[derive(Resource, Serialize, Deserialize, Default)]
+pub struct InputRecording {
+    pub start: RecordedStart,
+    pub frames: Vec<RecordedFrame>
+}
+
+// counts the fixed steps since the level was ready, stays empty while it's loading
+#[derive(Resource, Default)]
+pub struct ReplayClock {
+    pub frame: Option<usize>,
+    pub max_deviation: f32,
+    pub max_deviation_frame: usize
+}
+
+
+// the start only saves where the player and the camera are, so everything else has to
+// be the same on both ends: standing still under normal gravity with nothing buffered,
+// swung, grappled, grinded or equipped
+fn in_known_state(
+    state: PlayerState,
+    gravity_direction: &GravityDirection,
+    input_buffer: &InputBuffer,
+    swing: &Swing,
+    grapple: &Grapple,
+    rail_grind: &RailGrind,
+    upgrades: &PackUpgrades,
+) -> bool {
+    state == PlayerState::Idle
+        && gravity_direction.down == Vec3::NEG_Y
+        && gravity_direction.tilt == Quat::IDENTITY
+        && input_buffer.jump_time.is_none()
+        && input_buffer.swing_time.is_none()
+        && input_buffer.grapple_time.is_none()
+        && swing.swing_number == 0
+        && grapple.target.is_none()
+        && grapple.joint.is_none()
+        && rail_grind.rail.is_none()
+        && upgrades.equipped.is_none()
+}
+
+// the recording starts as soon as the level collisions are in place and the player has
+// landed in a known state, so the time spent loading doesn't change what gets recorded or replayed
+fn start_clock(
+    mut clock: ResMut<ReplayClock>,
+    replay: Res<InputReplay>,
+    mut recording: ResMut<InputRecording>,
+    mut player: Query<(
+        &mut Transform,
+        &mut LinearVelocity,
+        &PlayerState,
+        &GravityDirection,
+        &InputBuffer,
+        &Swing,
+        &Grapple,
+        &RailGrind,
+        &PackUpgrades
+    ), With<CharacterController>>,
+    mut camera: Query<(
+        &mut Transform,
+        &mut CameraIdentifier,
+        &mut CameraHeight,
+        &mut CameraRealHeight
+    ), Without<CharacterController>>,
+    level: Query<Entity, With<LevelCollider>>,
+    children: Query<&Children>,
+    colliders: Query<(), With<Collider>>,
+) {
+    if let Some(frame) = clock.frame.as_mut() {
+        *frame += 1;
+        return;
+    }
+
+    let Ok(level) = level.get_single() else {return;};
+    if !children.iter_descendants(level).any(|child| colliders.contains(child)) {return;}
+
+    let Ok((
+        mut transform,
+        mut velocity,
+        state,
+        gravity_direction,
+        input_buffer,
+        swing,
+        grapple,
+        rail_grind,
+        upgrades
+    )) = player.get_single_mut() else {return;};
+
+    if !in_known_state(*state, gravity_direction, input_buffer, swing, grapple, rail_grind, upgrades) {return;}
+    let Ok((mut camera_transform, mut camera_angle, mut camera_height, mut camera_real_height)) = camera.get_single_mut() else {return;};
+
+    match *replay {
+        InputReplay::Record(_) => {
+            recording.start = RecordedStart {
+                translation: transform.translation,
+                rotation: transform.rotation,
+                velocity: velocity.0,
+                camera_transform: *camera_transform,
+                camera_angle: camera_angle.0,
+                camera_height: camera_height.0,
+                camera_real_height: camera_real_height.0
+            };
+        }
+        InputReplay::Replay(_) => {
+            transform.translation = recording.start.translation;
+            transform.rotation = recording.start.rotation;
+            velocity.0 = recording.start.velocity;
+            *camera_transform = recording.start.camera_transform;
+            camera_angle.0 = recording.start.camera_angle;
+            camera_height.0 = recording.start.camera_height;
+            camera_real_height.0 = recording.start.camera_real_height;
+        }
+        InputReplay::Off => {}
+    }
+
+    clock.frame = Some(0);
+}
+
+fn record_frame(
+    clock: Res<ReplayClock>,
+    mut recording: ResMut<InputRecording>,
+    player: Query<(&PlayerIntent, &Transform), With<CharacterController>>,
+    camera: Query<&Transform, (With<CameraIdentifier>, Without<CharacterController>)>,
+) {
+    if clock.frame.is_none() {return;}
+
+    let Ok((intent, transform)) = player.get_single() else {return;};
+    let Ok(camera_transform) = camera.get_single() else {return;};
+
+    recording.frames.push(RecordedFrame {
+        intent: *intent,
+        camera_transform: *camera_transform,
+        translation: transform.translation
+    });
+}
+
+// the recording is saved when the game closes
+fn save_recording(
+    replay: Res<InputReplay>,
+    recording: Res<InputRecording>,
+    mut exit: EventReader<AppExit>,
+) {
+    if exit.read().next().is_some() {
+        let InputReplay::Record(path) = &*replay else {return;};
+
+        match ron::to_string(&*recording) {
+            Ok(file) => {
+                if let Err(error) = std::fs::write(path, file) {
+                    error!("couldn't save the recording {}: {}", path.display(), error);
+                }
+            }
+            Err(error) => error!("couldn't serialize the recording: {}", error),
+        }
+    }
+}
+
+// the camera moves every frame, so it's put back where it was for the step the same as the intent
+fn replay_intent(
+    clock: Res<ReplayClock>,
+    recording: Res<InputRecording>,
+    mut player: Query<&mut PlayerIntent, With<CharacterController>>,
+    mut camera: Query<&mut Transform, (With<CameraIdentifier>, Without<CharacterController>)>,
+) {
+    let Ok(mut intent) = player.get_single_mut() else {return;};
+
+    let recorded = clock.frame.and_then(|frame| recording.frames.get(frame));
+
+    *intent = recorded.map(|frame| frame.intent).unwrap_or_default();
+
+    if let (Some(recorded), Ok(mut camera_transform)) = (recorded, camera.get_single_mut()) {
+        *camera_transform = recorded.camera_transform;
+    }
+}
+
+// checks the replayed trajectory against the recorded one and quits when the recording ends
+fn compare_frame(
+    mut clock: ResMut<ReplayClock>,
+    recording: Res<InputRecording>,
+    outcome: Res<ReplayOutcome>,
+    player: Query<&Transform, With<CharacterController>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let Some(frame) = clock.frame else {return;};
+    let Ok(transform) = player.get_single() else {return;};
+
+    let Some(recorded) = recording.frames.get(frame) else {
+        if clock.max_deviation > MAX_DEVIATION {
+            error!(
+                "replay diverged: {} frames, max deviation {} at frame {}",
+                recording.frames.len(), clock.max_deviation, clock.max_deviation_frame
+            );
+
+            outcome.set_diverged();
+        }
+        else {
+            info!("replay matched the recording: {} frames", recording.frames.len());
+        }
+
+        exit.send(AppExit);
+        return;
+    };
+
+    let deviation = transform.translation.distance(recorded.translation);
+
+    if deviation > MAX_DEVIATION && clock.max_deviation <= MAX_DEVIATION {
+        warn!(
+            "replay diverged at frame {}: recorded {}, replayed {}",
+            frame, recorded.translation, transform.translation
+        );
+    }
+
+    if deviation > clock.max_deviation {
+        clock.max_deviation = deviation;
+        clock.max_deviation_frame = frame;
+    }
+}
@@ -0,0 +1,333 @@
+use bevy::{
+    asset::{io::Reader, ron, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::BoxedFuture
+};
+use bevy_xpbd_3d::prelude::*;
+use serde::Deserialize;
+
+use crate::{player::*, player_state::PlayerState};
+
+pub struct MovementTuningPlugin;
+
+impl Plugin for MovementTuningPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_asset::<MovementTuning>()
+            .register_asset_loader(MovementTuningLoader)
+            .init_resource::<MovementTuning>()
+            .add_systems(PreStartup, load_movement_tuning)
+            .add_systems(PreUpdate, apply_movement_tuning);
+    }
+}
+
+// everything that decides how the player moves, loaded from assets/player.tuning.ron
+// and reloaded when the file changes; until it's loaded the defaults below are used
+#[derive(Asset, Resource, TypePath, Deserialize, Clone)]
+#[serde(default)]
+pub struct MovementTuning {
+    pub acceleration: f32,
+    // how fast the player slows down, per second
+    pub damping: f32,
+    pub air_damping: f32,
+    pub highjump_damping: f32,
+    pub glide_damping: f32,
+    // how fast the player turns towards the stick, per second
+    pub turn_rate: f32,
+    pub crouch_turn_rate: f32,
+    pub strafe_turn_rate: f32,
+    pub glide_turn_rate: f32,
+    pub jump_impulse: f32,
+    pub double_jump_impulse: f32,
+    // in degrees
+    pub max_slope_angle: f32,
+    pub gravity_scale: f32,
+    pub max_step_height: f32,
+    // how far below the ground caster the player is pulled back to the ground
+    pub ground_snap_distance: f32,
+    pub crouch_height: f32,
+    // part of the walking acceleration used while crouching, slow enough not to count as a longjump run-up
+    pub crouch_walk_factor: f32,
+
+    pub sideflip_impulse: f32,
+    pub sideflip_push: f32,
+    pub longjump_impulse: f32,
+    pub longjump_speed: f32,
+    pub longjump_gravity_scale: f32,
+    pub highjump_impulse: f32,
+    pub highjump_second_impulse: f32,
+    pub highjump_gravity_scale: f32,
+    pub highjump_second_gravity_scale: f32,
+    pub glide_fall_speed: f32,
+    pub heli_fall_speed: f32,
+    pub heli_glide_speed: f32,
+    pub heli_spin_boost: f32,
+    pub thruster_fall_speed: f32,
+    pub thruster_glide_speed: f32,
+    pub thruster_highjump_boost: f32,
+    pub swing_push: f32,
+    pub slide_acceleration: f32,
+    pub slide_steering: f32,
+    pub slide_damping: f32,
+    // how much ice and mud change the grip on the ground
+    pub ice_damping_scale: f32,
+    pub ice_acceleration_scale: f32,
+    pub mud_damping_scale: f32,
+    pub mud_acceleration_scale: f32,
+    // how far the player walks between footsteps
+    pub footstep_stride: f32,
+    pub slide_jump_impulse: f32,
+    pub slide_jump_push: f32,
+    // from the center of the player
+    pub wall_check_distance: f32,
+    pub wall_slide_speed: f32,
+    pub wall_jump_impulse: f32,
+    pub wall_jump_push: f32,
+    // how high above the player a ledge can be grabbed, and how low it has to be at least
+    pub ledge_reach: f32,
+    pub ledge_min_height: f32,
+    // how far below the edge the player hangs, and how far above it the player ends up after climbing
+    pub ledge_hang_depth: f32,
+    pub ledge_climb_height: f32,
+    pub shimmy_speed: f32,
+    // the slowest the player grinds, it keeps any faster speed it lands on the rail with
+    pub grind_speed: f32,
+    // how far from the feet a rail is caught
+    pub grind_attach_distance: f32,
+    pub rail_hop_impulse: f32,
+    pub rail_hop_push: f32,
+    pub swingshot_range: f32,
+    pub swingshot_pull_speed: f32,
+    // how close to a pull point the swingshot lets go
+    pub swingshot_arrive_distance: f32,
+    // part of the walking acceleration used to pump a swing
+    pub swingshot_swing_control: f32,
+    // how far below the surface the center of the player floats
+    pub swim_float_depth: f32,
+    pub swim_acceleration: f32,
+    pub swim_turn_rate: f32,
+    // how hard the water pushes the player back to the surface
+    pub swim_buoyancy: f32,
+    pub swim_jump_impulse: f32,
+    pub dive_impulse: f32,
+    pub dive_acceleration: f32,
+    // how fast a diving player floats up when not swimming
+    pub dive_buoyancy: f32,
+    pub water_damping: f32,
+    pub climb_speed: f32,
+    pub climb_lateral_speed: f32,
+    pub climb_jump_impulse: f32,
+    pub climb_jump_push: f32,
+    // how fast the player turns to a new gravity direction
+    pub gravity_turn_rate: f32,
+
+    // timing windows in seconds
+    pub double_jump_window: f32,
+    pub ledge_regrab_delay: f32,
+    pub rail_regrab_delay: f32,
+    pub climb_regrab_delay: f32,
+    pub longjump_time: f32,
+    pub highjump_time: f32,
+    pub highjump_second_time: f32,
+    pub input_buffer: f32,
+    pub coyote_time: f32,
+    // how long the player can stay underwater and how long it takes to catch its breath
+    pub oxygen_time: f32,
+    pub oxygen_refill_time: f32
+}
+
+impl Default for MovementTuning {
+    fn default() -> Self {
+        Self {
+            acceleration: 65.,
+            damping: 5.,
+            air_damping: 4.55,
+            highjump_damping: 9.75,
+            glide_damping: 6.32,
+            turn_rate: 4.35,
+            crouch_turn_rate: 0.42,
+            strafe_turn_rate: 8.36,
+            glide_turn_rate: 2.5,
+            jump_impulse: 11.2,
+            double_jump_impulse: 9.,
+            max_slope_angle: 45.,
+            gravity_scale: 3.,
+            max_step_height: 0.35,
+            ground_snap_distance: 0.3,
+            crouch_height: 0.85,
+            crouch_walk_factor: 0.12,
+
+            sideflip_impulse: 12.2,
+            sideflip_push: 4.,
+            longjump_impulse: 5.5,
+            longjump_speed: 14.7,
+            longjump_gravity_scale: 1.,
+            highjump_impulse: 6.5,
+            highjump_second_impulse: 3.8,
+            highjump_gravity_scale: 1.3,
+            highjump_second_gravity_scale: 1.5,
+            glide_fall_speed: 1.35,
+            heli_fall_speed: 0.6,
+            heli_glide_speed: 5.,
+            heli_spin_boost: 3.5,
+            thruster_fall_speed: 0.9,
+            thruster_glide_speed: 12.,
+            thruster_highjump_boost: 4.,
+            swing_push: 5.,
+            slide_acceleration: 18.,
+            slide_steering: 8.,
+            slide_damping: 1.2,
+            ice_damping_scale: 0.15,
+            ice_acceleration_scale: 0.2,
+            mud_damping_scale: 1.6,
+            mud_acceleration_scale: 0.9,
+            footstep_stride: 1.6,
+            slide_jump_impulse: 9.,
+            slide_jump_push: 7.,
+            wall_check_distance: 0.55,
+            wall_slide_speed: 2.5,
+            wall_jump_impulse: 11.,
+            wall_jump_push: 7.,
+            ledge_reach: 1.3,
+            ledge_min_height: 0.3,
+            ledge_hang_depth: 0.75,
+            ledge_climb_height: 0.65,
+            shimmy_speed: 2.,
+            grind_speed: 10.,
+            grind_attach_distance: 0.5,
+            rail_hop_impulse: 8.,
+            rail_hop_push: 6.,
+            swingshot_range: 14.,
+            swingshot_pull_speed: 16.,
+            swingshot_arrive_distance: 1.2,
+            swingshot_swing_control: 0.25,
+            swim_float_depth: 0.4,
+            swim_acceleration: 12.,
+            swim_turn_rate: 6.,
+            swim_buoyancy: 40.,
+            swim_jump_impulse: 9.,
+            dive_impulse: 4.,
+            dive_acceleration: 10.,
+            dive_buoyancy: 1.5,
+            water_damping: 3.,
+            climb_speed: 3.,
+            climb_lateral_speed: 2.,
+            climb_jump_impulse: 9.,
+            climb_jump_push: 6.,
+            gravity_turn_rate: 6.,
+
+            double_jump_window: 0.65,
+            ledge_regrab_delay: 0.4,
+            rail_regrab_delay: 0.4,
+            climb_regrab_delay: 0.4,
+            longjump_time: 1.65,
+            highjump_time: 1.42,
+            highjump_second_time: 0.7,
+            input_buffer: 0.15,
+            coyote_time: 0.12,
+            oxygen_time: 20.,
+            oxygen_refill_time: 4.
+        }
+    }
+}
+
+// handle to the tuning file used by the player
+#[derive(Resource)]
+pub struct MovementTuningHandle(pub Handle<MovementTuning>);
+
+
+#[derive(Default)]
+pub struct MovementTuningLoader;
+
+impl AssetLoader for MovementTuningLoader {
+    type Asset = MovementTuning;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a Self::Settings,
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+
+            Ok(ron::de::from_bytes::<MovementTuning>(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tuning.ron"]
+    }
+}
+
+
+fn load_movement_tuning(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+) {
+    commands.insert_resource(MovementTuningHandle(assets.load("player.tuning.ron")));
+}
+
+// copies the tuning file into the resource every time it's loaded or changed
+// and updates the values the player keeps in its components
+fn apply_movement_tuning(
+    mut events: EventReader<AssetEvent<MovementTuning>>,
+    tunings: Res<Assets<MovementTuning>>,
+    handle: Res<MovementTuningHandle>,
+    mut tuning: ResMut<MovementTuning>,
+    mut player: Query<(
+        &mut MovementAcceleration,
+        &mut MovementDampingFactor,
+        &mut JumpImpulse,
+        &mut DoubleJumpImpulse,
+        &mut MaxSlopeAngle,
+        &mut MaxStepHeight,
+        &mut GroundSnapDistance,
+        &mut CrouchHeight,
+        &mut InputBuffer,
+        &mut CoyoteTime,
+        &mut GravityScale,
+        &PlayerState
+    ), With<CharacterController>>,
+) {
+    for event in events.read() {
+        if !event.is_loaded_with_dependencies(&handle.0) && !event.is_modified(&handle.0) {continue;}
+
+        let Some(loaded) = tunings.get(&handle.0) else {continue;};
+
+        *tuning = loaded.clone();
+
+        let Ok((
+            mut acceleration,
+            mut damping,
+            mut jump_impulse,
+            mut double_jump_impulse,
+            mut max_slope_angle,
+            mut max_step_height,
+            mut ground_snap_distance,
+            mut crouch_height,
+            mut input_buffer,
+            mut coyote_time,
+            mut gravity_scale,
+            state
+        )) = player.get_single_mut() else {continue;};
+
+        acceleration.0 = tuning.acceleration;
+        damping.0 = tuning.damping;
+        jump_impulse.0 = tuning.jump_impulse;
+        double_jump_impulse.0 = tuning.double_jump_impulse;
+        max_slope_angle.0 = tuning.max_slope_angle.to_radians();
+        max_step_height.0 = tuning.max_step_height;
+        ground_snap_distance.0 = tuning.ground_snap_distance;
+        crouch_height.0 = tuning.crouch_height;
+        input_buffer.window = tuning.input_buffer;
+        coyote_time.window = tuning.coyote_time;
+
+        gravity_scale.0 = state.gravity_scale(&tuning);
+
+        info!("movement tuning reloaded");
+    }
+}
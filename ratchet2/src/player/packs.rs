@@ -0,0 +1,140 @@
+use bevy::{audio::{PlaybackMode, Volume}, prelude::*};
+
+use crate::{level::LevelNodes, player::{CharacterController, PlayerIntent}, player_controls::playing};
+
+pub struct PackPlugin;
+
+impl Plugin for PackPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, (
+                spawn_pack_pickups,
+                equip_pack.run_if(playing)
+            ))
+            .add_systems(FixedUpdate, pick_up_packs.run_if(playing));
+    }
+}
+
+// how close the player has to get to a pickup to take it
+const PICKUP_DISTANCE: f32 = 1.2;
+
+// the upgrades the player can wear on its back, they change how it glides and jumps
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Pack {
+    // hovers slowly, steers the glide and spins higher on a double jump
+    Heli,
+    // glides fast and far and boosts the highjump
+    Thruster
+}
+
+// the packs the player has found and the one it's wearing,
+// with none equipped the player glides the default way
+#[derive(Component, Default)]
+pub struct PackUpgrades {
+    pub unlocked: Vec<Pack>,
+    pub equipped: Option<Pack>
+}
+
+impl PackUpgrades {
+    pub fn unlock(&mut self, pack: Pack) {
+        if !self.unlocked.contains(&pack) {
+            self.unlocked.push(pack);
+        }
+    }
+
+    // goes through the unlocked packs in the order they were found, then back to none
+    pub fn cycle(&mut self) {
+        self.equipped = match self.equipped {
+            None => self.unlocked.first().copied(),
+            Some(pack) => {
+                let index = self.unlocked.iter().position(|unlocked| *unlocked == pack).unwrap_or(0);
+                self.unlocked.get(index + 1).copied()
+            }
+        };
+    }
+}
+
+// a pack lying in the level
+#[derive(Component)]
+pub struct PackPickup(pub Pack);
+
+
+// pack pickups are authored in the level collisions as empties named "pack_heli..." or "pack_thruster...",
+// the collisions are hidden so every pickup gets its own mesh
+fn spawn_pack_pickups(
+    mut level_nodes: LevelNodes,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (node, name) in level_nodes.spawned() {
+        let (pack, color) = if name.starts_with("pack_heli") {
+            (Pack::Heli, Color::rgb(0.9, 0.75, 0.2))
+        }
+        else if name.starts_with("pack_thruster") {
+            (Pack::Thruster, Color::rgb(0.85, 0.3, 0.2))
+        }
+        else {
+            continue;
+        };
+
+        let Some(transform) = level_nodes.global_transform(node) else {continue;};
+
+        commands.spawn((
+            PbrBundle {
+                mesh: meshes.add(Sphere::new(0.3)),
+                material: materials.add(StandardMaterial {
+                    base_color: color,
+                    ..default()
+                }),
+                transform: Transform::from_translation(transform.translation()),
+                ..default()
+            },
+            PackPickup(pack)
+        ));
+    }
+}
+
+// walking into a pickup unlocks the pack and puts it on
+fn pick_up_packs(
+    mut player: Query<(&Transform, &mut PackUpgrades), With<CharacterController>>,
+    pickups: Query<(Entity, &PackPickup, &Transform), Without<CharacterController>>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+) {
+    let Ok((transform, mut packs)) = player.get_single_mut() else {return;};
+
+    for (pickup_entity, pickup, pickup_transform) in pickups.iter() {
+        if pickup_transform.translation.distance(transform.translation) > PICKUP_DISTANCE {continue;}
+
+        packs.unlock(pickup.0);
+        packs.equipped = Some(pickup.0);
+
+        commands.entity(pickup_entity).despawn_recursive();
+
+        commands.spawn((
+            AudioBundle {
+                source: asset_server.load("pickup.ogg"),
+                settings: PlaybackSettings {
+                    volume: Volume::new(0.07),
+                    mode: PlaybackMode::Despawn,
+                    ..default()
+                }
+            },
+        ));
+
+        info!("picked up the {:?} pack", pickup.0);
+    }
+}
+
+fn equip_pack(
+    mut player: Query<(&PlayerIntent, &mut PackUpgrades), With<CharacterController>>,
+) {
+    let Ok((intent, mut packs)) = player.get_single_mut() else {return;};
+
+    if intent.pack_pressed && !packs.unlocked.is_empty() {
+        packs.cycle();
+
+        info!("equipped pack: {:?}", packs.equipped);
+    }
+}
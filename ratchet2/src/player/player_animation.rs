@@ -2,7 +2,7 @@ use bevy::{
     prelude::*, utils::Duration
 };
 
-use crate::{player::{Animation, CharacterController, CurrentAnimation, PlayerAnimations}, player_input::*};
+use crate::{player::{Animation, CharacterController, CurrentAnimation, PlayerAnimations}, player_input::Swing, player_state::{change_state, PlayerState, StateChanged}};
 
 
 pub struct PlayerAnimationPlugin;
@@ -18,15 +18,47 @@ impl Plugin for PlayerAnimationPlugin {
 }
 
 
+// the states that last as long as their animation end in the fixed step after it finished,
+// so the state only ever changes with the rest of the character
+pub fn finish_animations(
+    animation_player: Query<&AnimationPlayer>,
+    mut player: Query<(Entity, &CurrentAnimation, &mut Swing, &mut PlayerState), With<CharacterController>>,
+    children: Query<&Children>,
+    mut state_changed: EventWriter<StateChanged>,
+) {
+    let Ok((player, current_animation, mut swing, mut state)) = player.get_single_mut() else {return;};
+
+    let finished = children.iter_descendants(player)
+        .any(|child| animation_player.get(child).is_ok_and(|animation_player| animation_player.is_finished()));
+
+    if !finished {return;}
+
+    // the animation only counts once it's been picked for the current state
+    match (*state, &current_animation.0) {
+        (PlayerState::Jump, Animation::Jump)
+        | (PlayerState::DoubleJump, Animation::DoubleJump)
+        | (PlayerState::SideflipL, Animation::SideFlipL)
+        | (PlayerState::SideflipR, Animation::SideFlipR) => {
+            change_state(player, &mut state, PlayerState::Fall, &mut state_changed);
+        }
+        (PlayerState::Land, Animation::Land) => {
+            change_state(player, &mut state, PlayerState::Idle, &mut state_changed);
+        }
+        (PlayerState::Swing, Animation::Swing) if swing.swing_number == 3 => {
+            swing.swing_number = 0;
+        }
+        _ => {}
+    }
+}
+
 fn play_animations(
     mut animation_player: Query<&mut AnimationPlayer>,
-    mut player: Query<(Entity, &CurrentAnimation, &mut Swing), With<CharacterController>>,
+    player: Query<(Entity, &CurrentAnimation, &Swing), With<CharacterController>>,
     children: Query<&Children>,
-    mut commands: Commands,
     animations: Res<PlayerAnimations>,
 
 ) {
-    let Ok((player, current_animation, mut swing)) = player.get_single_mut() else {return;};
+    let Ok((player, current_animation, swing)) = player.get_single() else {return;};
 
     for child in children.iter_descendants(player) {
         if let Ok(mut animation_player) = animation_player.get_mut(child) {
@@ -38,26 +70,13 @@ fn play_animations(
                 Animation::Jump => {
 
                     animation_player.play_with_transition(animations.0[5].clone_weak(), Duration::from_millis(80));
-                    
-                    if animation_player.is_finished() {
-                        commands.entity(player).remove::<Jump>();
-                    }
                 }
                 Animation::DoubleJump => {
 
                     animation_player.play_with_transition(animations.0[1].clone_weak(), Duration::from_millis(80));
-                    
-                    if animation_player.is_finished() {
-                        commands.entity(player).remove::<DoubleJump>();
-                    }
                 }
                 Animation::Land => {
                     animation_player.play_with_transition(animations.0[6].clone_weak(), Duration::from_millis(150));
-                    if animation_player.is_finished() {
-                        commands.entity(player).remove::<Land>();
-                        animation_player.play_with_transition(animations.0[4].clone_weak(), Duration::from_millis(150));
-
-                    }
                 }
                 Animation::Walk => {
                     
@@ -73,15 +92,9 @@ fn play_animations(
                 }
                 Animation::SideFlipL => {
                     animation_player.play_with_transition(animations.0[8].clone_weak(), Duration::from_millis(150));
-                    if animation_player.is_finished() {
-                        commands.entity(player).remove::<SideflipL>();
-                    }
                 }
                 Animation::SideFlipR => {
                     animation_player.play_with_transition(animations.0[9].clone_weak(), Duration::from_millis(150));
-                    if animation_player.is_finished() {
-                        commands.entity(player).remove::<SideflipR>();
-                    }
                 }
                 Animation::Longjump => {
                     animation_player.play_with_transition(animations.0[7].clone_weak(), Duration::from_millis(150));
@@ -91,14 +104,24 @@ fn play_animations(
                     animation_player.play_with_transition(animations.0[3].clone_weak(), Duration::from_millis(150));
                     
                 }
+                Animation::Slide => {
+                    animation_player.play_with_transition(animations.0[18].clone_weak(), Duration::from_millis(150)).repeat();
+                }
+                Animation::Grind => {
+                    animation_player.play_with_transition(animations.0[14].clone_weak(), Duration::from_millis(100)).repeat();
+                }
+                Animation::Swim => {
+                    animation_player.play_with_transition(animations.0[15].clone_weak(), Duration::from_millis(200)).repeat();
+                }
+                Animation::Dive => {
+                    animation_player.play_with_transition(animations.0[16].clone_weak(), Duration::from_millis(200)).repeat();
+                }
+                Animation::Climb => {
+                    animation_player.play_with_transition(animations.0[17].clone_weak(), Duration::from_millis(100)).repeat();
+                }
                 Animation::Swing => {
                     let anim = 9 + swing.swing_number;
                     animation_player.play(animations.0[anim as usize].clone_weak()).set_speed(1.3);
-
-                    if animation_player.is_finished() && swing.swing_number == 3 {
-                        swing.swing_number = 0;
-                    }
-
                 }
                 
             }
@@ -110,84 +133,31 @@ fn play_animations(
 fn animation_selector(
     mut states: Query<(
         &mut CurrentAnimation,
-        Has<Idle>,
-        Has<Walk>,
-        Has<Land>,
-        Has<Grounded>,
-        Has<Falling>,
-        Has<Jump>,
-        Has<DoubleJump>,
-        Has<Crouch>,
-        Has<SideflipL>,
-        Has<SideflipR>,
-        Has<Longjump>,
-        Has<Highjump>,
+        &PlayerState,
         &Swing
     ), With<CharacterController>>
 ) {
-    let Ok((
-        mut current_animation,
-        idle,
-        walking,
-        landing,
-        grounded,
-        falling,
-        jumping,
-        doublejumping,
-        crouching,
-        sideflip_l,
-        sideflip_r,
-        longjump,
-        highjump,
-        swing
-    )) = states.get_single_mut() else {return;};
-
-
-    if grounded {
-        if swing.swing_number > 0 {
-            current_animation.0 = Animation::Swing;
-        }
-        else if crouching {
-            current_animation.0 = Animation::Crouch;
-        }
-        else if walking {
-            current_animation.0 = Animation::Walk;
-            
-        }
-        else if landing {
-            current_animation.0 = Animation::Land;
-        }
-        else if idle {
-            current_animation.0 = Animation::Idle;
-        }
-        
-    }
-    else {
-        if sideflip_l {
-            current_animation.0 = Animation::SideFlipL;
-        }
-        else if sideflip_r {
-            current_animation.0 = Animation::SideFlipR;
-
-        }
-        else if longjump {
-            current_animation.0 = Animation::Longjump;
+    let Ok((mut current_animation, state, swing)) = states.get_single_mut() else {return;};
+
+    current_animation.0 = match state {
+        PlayerState::Idle => Animation::Idle,
+        PlayerState::Walk | PlayerState::Strafe => Animation::Walk,
+        PlayerState::Crouch => Animation::Crouch,
+        PlayerState::Land => Animation::Land,
+        PlayerState::Swing if swing.swing_number > 0 => Animation::Swing,
+        PlayerState::Swing => Animation::Idle,
+        PlayerState::Jump => Animation::Jump,
+        PlayerState::DoubleJump => Animation::DoubleJump,
+        PlayerState::SideflipL => Animation::SideFlipL,
+        PlayerState::SideflipR => Animation::SideFlipR,
+        PlayerState::Longjump => Animation::Longjump,
+        PlayerState::Highjump => Animation::Highjump,
+        PlayerState::Slide => Animation::Slide,
+        PlayerState::Grind => Animation::Grind,
+        PlayerState::Swim => Animation::Swim,
+        PlayerState::Dive => Animation::Dive,
+        PlayerState::Climb => Animation::Climb,
+        PlayerState::Glide | PlayerState::Fall | PlayerState::WallSlide | PlayerState::LedgeHang | PlayerState::Swingshot => Animation::Fall
+    };
 
-        }
-        else if highjump {
-            current_animation.0 = Animation::Highjump;
-
-        }
-        else if jumping {
-            current_animation.0 = Animation::Jump;
-        }
-        else if doublejumping {
-            current_animation.0 = Animation::DoubleJump;
-        }
-        else if falling {
-            current_animation.0 = Animation::Fall;
-            
-        }
-    }
-
-}
\ No newline at end of file
+}
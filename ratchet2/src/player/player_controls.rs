@@ -0,0 +1,234 @@
+use bevy::{
+    ecs::system::SystemParam,
+    input::{gamepad::{GamepadConnection, GamepadConnectionEvent}, mouse::MouseMotion, InputSystem},
+    prelude::*,
+    window::{CursorGrabMode, PrimaryWindow}
+};
+use bevy_xpbd_3d::prelude::{Physics, PhysicsTime};
+
+use crate::{input_map::{InputMap, InputMapHandle, InputMapPlugin}, player::{CharacterController, PlayerIntent}};
+
+pub struct PlayerControlsPlugin;
+
+impl Plugin for PlayerControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_plugins(InputMapPlugin)
+            .add_systems(PreUpdate, (
+                assign_gamepad,
+                read_player_intent
+            ).chain().after(InputSystem))
+            .add_systems(Update, grab_cursor);
+    }
+}
+
+const MOUSE_SENSITIVITY: f32 = 0.002;
+
+// the only gamepad that drives the character
+#[derive(Component)]
+pub struct ControlledBy(pub Gamepad);
+
+// the game runs only while it isn't paused
+pub fn playing(time: Res<Time<Virtual>>) -> bool {
+    !time.is_paused()
+}
+
+// reads the gamepads, the keyboard and the mouse buttons as a single controller,
+// looking up every action by name in the input map
+#[derive(SystemParam)]
+pub struct PlayerControls<'w> {
+    axes: Res<'w, Axis<GamepadAxis>>,
+    buttons: Res<'w, ButtonInput<GamepadButton>>,
+    keys: Res<'w, ButtonInput<KeyCode>>,
+    mouse_buttons: Res<'w, ButtonInput<MouseButton>>,
+    input_maps: Res<'w, Assets<InputMap>>,
+    input_map: Res<'w, InputMapHandle>,
+}
+
+impl<'w> PlayerControls<'w> {
+
+    pub fn pressed(&self, gamepad: Option<Gamepad>, action: &str) -> bool {
+        let Some(binding) = self.input_map().and_then(|map| map.buttons.get(action)) else {return false;};
+
+        binding.gamepad.iter().any(|button| {
+            gamepad.is_some_and(|gamepad| self.buttons.pressed(GamepadButton::new(gamepad, *button)))
+        })
+        || self.keys.any_pressed(binding.keys.iter().copied())
+        || self.mouse_buttons.any_pressed(binding.mouse.iter().copied())
+    }
+
+    pub fn just_pressed(&self, gamepad: Option<Gamepad>, action: &str) -> bool {
+        let Some(binding) = self.input_map().and_then(|map| map.buttons.get(action)) else {return false;};
+
+        binding.gamepad.iter().any(|button| {
+            gamepad.is_some_and(|gamepad| self.buttons.just_pressed(GamepadButton::new(gamepad, *button)))
+        })
+        || self.keys.any_just_pressed(binding.keys.iter().copied())
+        || self.mouse_buttons.any_just_pressed(binding.mouse.iter().copied())
+    }
+
+    // the keys when any of them is held, the gamepad stick otherwise,
+    // passed through the deadzones and curve of the binding
+    pub fn stick(&self, gamepad: Option<Gamepad>, action: &str) -> Vec2 {
+        let Some(binding) = self.input_map().and_then(|map| map.sticks.get(action)) else {return Vec2::ZERO;};
+
+        if let Some(keys) = &binding.keys {
+            let keyboard = Vec2::new(
+                self.key_axis(keys.right, keys.left),
+                self.key_axis(keys.up, keys.down)
+            );
+
+            if keyboard != Vec2::ZERO {
+                return keyboard.normalize();
+            }
+        }
+
+        let (Some(axes), Some(gamepad)) = (&binding.gamepad, gamepad) else {return Vec2::ZERO;};
+
+        match (
+            self.axes.get(GamepadAxis::new(gamepad, axes.x)),
+            self.axes.get(GamepadAxis::new(gamepad, axes.y))
+        ) {
+            (Some(x), Some(y)) => binding.response.apply(Vec2::new(x, y)),
+            _ => Vec2::ZERO
+        }
+    }
+
+    // the input map stays empty until the file is loaded
+    fn input_map(&self) -> Option<&InputMap> {
+        self.input_maps.get(&self.input_map.0)
+    }
+
+    fn key_axis(&self, positive: KeyCode, negative: KeyCode) -> f32 {
+        let mut value = 0.;
+
+        if self.keys.pressed(positive) {
+            value += 1.;
+        }
+        if self.keys.pressed(negative) {
+            value -= 1.;
+        }
+
+        value
+    }
+}
+
+
+// binds a gamepad to the character when it connects, or when any of its buttons is pressed
+// while no gamepad is bound; if the bound gamepad disconnects the game is paused until
+// another one takes its place or the player carries on with the keyboard or the mouse
+fn assign_gamepad(
+    mut commands: Commands,
+    mut connections: EventReader<GamepadConnectionEvent>,
+    buttons: Res<ButtonInput<GamepadButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    player: Query<(Entity, Option<&ControlledBy>), With<CharacterController>>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+    mut physics_time: ResMut<Time<Physics>>,
+    // only the pause made here is lifted here, a pause from anywhere else stays
+    mut paused_for_gamepad: Local<bool>,
+) {
+    let Ok((player, controlled_by)) = player.get_single() else {return;};
+
+    let mut active_gamepad = controlled_by.map(|controlled_by| controlled_by.0);
+
+    for connection in connections.read() {
+        match connection.connection {
+            GamepadConnection::Connected(_) => {
+                if active_gamepad.is_none() {
+                    active_gamepad = Some(connection.gamepad);
+                    commands.entity(player).insert(ControlledBy(connection.gamepad));
+                }
+            }
+            GamepadConnection::Disconnected => {
+                if active_gamepad == Some(connection.gamepad) {
+                    active_gamepad = None;
+                    commands.entity(player).remove::<ControlledBy>();
+
+                    if !virtual_time.is_paused() {
+                        *paused_for_gamepad = true;
+                        virtual_time.pause();
+                        physics_time.pause();
+                    }
+                }
+            }
+        }
+    }
+
+    if active_gamepad.is_none() {
+        if let Some(button) = buttons.get_just_pressed().next() {
+            active_gamepad = Some(button.gamepad);
+            commands.entity(player).insert(ControlledBy(button.gamepad));
+        }
+    }
+
+    let keyboard_used = keys.get_just_pressed().next().is_some() || mouse_buttons.get_just_pressed().next().is_some();
+
+    if *paused_for_gamepad && (active_gamepad.is_some() || keyboard_used) {
+        *paused_for_gamepad = false;
+        virtual_time.unpause();
+        physics_time.unpause();
+    }
+}
+
+// turns whatever device is being used into the player intent for this frame
+fn read_player_intent(
+    controls: PlayerControls,
+    mut mouse_motion: EventReader<MouseMotion>,
+    mut player: Query<(&mut PlayerIntent, Option<&ControlledBy>)>,
+    time: Res<Time<Virtual>>,
+) {
+    let Ok((mut intent, controlled_by)) = player.get_single_mut() else {return;};
+
+    // the input is dropped while the game is paused waiting for a gamepad
+    if time.is_paused() {
+        *intent = PlayerIntent::default();
+        mouse_motion.clear();
+        return;
+    }
+
+    let gamepad = controlled_by.map(|controlled_by| controlled_by.0);
+
+    // the stick is scaled by the frame time and the mouse by its sensitivity
+    // so the camera gets how much to look around this frame from either one
+    let mut look = controls.stick(gamepad, "look") * time.delta_seconds();
+
+    for motion in mouse_motion.read() {
+        // the mouse y points down, the sticks point up
+        look += Vec2::new(motion.delta.x, -motion.delta.y) * MOUSE_SENSITIVITY;
+    }
+
+    *intent = PlayerIntent {
+        movement: controls.stick(gamepad, "move"),
+        look,
+        jump_pressed: controls.just_pressed(gamepad, "jump"),
+        jump_held: controls.pressed(gamepad, "jump"),
+        crouch: controls.pressed(gamepad, "crouch"),
+        strafe: controls.pressed(gamepad, "strafe"),
+        fire: controls.pressed(gamepad, "fire"),
+        swing_pressed: controls.just_pressed(gamepad, "swing"),
+        grapple_pressed: controls.just_pressed(gamepad, "grapple"),
+        grapple_held: controls.pressed(gamepad, "grapple"),
+        pack_pressed: controls.just_pressed(gamepad, "pack"),
+    };
+}
+
+// locks the cursor to the window when clicking it and frees it with escape
+fn grab_cursor(
+    mut window: Query<&mut Window, With<PrimaryWindow>>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+) {
+    let Ok(mut window) = window.get_single_mut() else {return;};
+
+    if mouse_buttons.just_pressed(MouseButton::Left) {
+        window.cursor.grab_mode = CursorGrabMode::Locked;
+        window.cursor.visible = false;
+    }
+
+    if keys.just_pressed(KeyCode::Escape) {
+        window.cursor.grab_mode = CursorGrabMode::None;
+        window.cursor.visible = true;
+    }
+}
@@ -0,0 +1,60 @@
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::{Gravity, GravityScale, LinearVelocity};
+
+use crate::{gravity_volumes::GravityVolume, movement_tuning::MovementTuning, player::*, player_controls::playing, player_input::update_grounded, player_movement::{decay, step_up}};
+
+pub struct PlayerGravityPlugin;
+
+impl Plugin for PlayerGravityPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(FixedUpdate, (
+                stand_upright,
+                follow_gravity_volumes
+            ).chain().before(update_grounded).run_if(playing))
+            .add_systems(FixedUpdate, lean_to_gravity.after(step_up).run_if(playing));
+    }
+}
+
+// the gravity volume the player is in decides which way is down, outside of them it's the world down,
+// the player turns to it gradually so stepping onto a walkway doesn't throw the camera around
+fn follow_gravity_volumes(
+    mut player: Query<(&Transform, &mut GravityDirection, &mut LinearVelocity, &GravityScale), With<CharacterController>>,
+    volumes: Query<&GravityVolume>,
+    gravity: Res<Gravity>,
+    tuning: Res<MovementTuning>,
+    time: Res<Time>
+) {
+    let Ok((transform, mut gravity_direction, mut linear_velocity, gravity_scale)) = player.get_single_mut() else {return;};
+
+    let target = volumes.iter()
+        .find_map(|volume| volume.down_at(transform.translation))
+        .unwrap_or(Vec3::NEG_Y);
+
+    let turn = Quat::from_rotation_arc(gravity_direction.down, target);
+    let down = (Quat::IDENTITY.slerp(turn, 1. - decay(tuning.gravity_turn_rate, &time)) * gravity_direction.down).normalize();
+    gravity_direction.turn_to(down);
+
+    // the physics pulls every body down the world gravity, the difference is made up here
+    let pull = gravity_direction.down * gravity.0.length() - gravity.0;
+    linear_velocity.0 += pull * gravity_scale.0 * time.delta_seconds();
+}
+
+// the character systems turn the player around as if it were standing upright,
+// it's only leaned over to its gravity once they're done
+fn stand_upright(
+    mut player: Query<(&mut Transform, &GravityDirection), With<CharacterController>>,
+) {
+    let Ok((mut transform, gravity_direction)) = player.get_single_mut() else {return;};
+
+    transform.rotation = gravity_direction.tilt.inverse() * transform.rotation;
+}
+
+// the collider and the ground caster lean with the player, so it stands on whatever gravity pulls it to
+fn lean_to_gravity(
+    mut player: Query<(&mut Transform, &GravityDirection), With<CharacterController>>,
+) {
+    let Ok((mut transform, gravity_direction)) = player.get_single_mut() else {return;};
+
+    transform.rotation = gravity_direction.tilt * transform.rotation;
+}
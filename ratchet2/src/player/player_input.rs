@@ -1,7 +1,7 @@
 use bevy::{audio::{PlaybackMode, Volume}, prelude::*};
 use bevy_xpbd_3d::{math::*, prelude::*};
 
-use crate::{camera::CameraIdentifier, player::*};
+use crate::{camera::CameraIdentifier, climbing::{Climbable, Ladder}, movement_tuning::MovementTuning, player::*, player_animation::finish_animations, player_controls::playing, player_movement::decay, player_state::*, rails::Rail, surfaces::{Surface, SurfaceMaterial}, water::WaterVolume, wind::WindVolume};
 
 
 pub struct PlayerInputPlugin;
@@ -9,70 +9,42 @@ pub struct PlayerInputPlugin;
 impl Plugin for PlayerInputPlugin {
     fn build(&self, app: &mut App) {
         app
-            .add_systems(Update, (
-                update_grounded,
-                crouch,
-                walk,
-                strafe,
-                sideflips,
-                longjump,
-                jump,
-                doublejump,
-                highjump,
-                gliding,
-                
-            ).chain());
-    }
-}
-
-// identifier for the glide audio player 
-#[derive(Component)]
-pub struct GlideAudio;
-
-#[derive(Component)]
-pub struct Slide;
-
-#[derive(Component)]
-pub struct Idle;
-
-#[derive(Component)]
-pub struct Walk;
-
-#[derive(Component)]
-pub struct Strafe;
-
-#[derive(Component)]
-pub struct Crouch;
-
-#[derive(Component)]
-pub struct SideflipL;
-
-#[derive(Component)]
-pub struct SideflipR;
-
-#[derive(Component)]
-pub struct Longjump;
-
-#[derive(Component)]
-pub struct Highjump;
-
-#[derive(Component)]
-pub struct Jump;
-
-#[derive(Component)]
-pub struct DoubleJump;
-
-#[derive(Component)]
-pub struct Glide;
-
-#[derive(Component)]
-pub struct Grounded;
-
-#[derive(Component)]
-pub struct Falling;
-
-#[derive(Component)]
-pub struct Land;
+            // presses are caught every frame, even the ones without a fixed step
+            .add_systems(Update, buffer_input.run_if(playing))
+            // grouped since a tuple of systems can't be longer than 20
+            .add_systems(FixedUpdate, (
+                // what the player is standing on and touching, and the moves its animations ended
+                (
+                    update_grounded,
+                    finish_animations,
+                    update_ground_surface,
+                    ride_platform,
+                    detect_walls
+                ).chain(),
+                // what it does about it
+                (
+                    grind,
+                    swim,
+                    climb,
+                    crouch,
+                    walk,
+                    strafe,
+                    sideflips,
+                    longjump,
+                    jump,
+                    doublejump,
+                    highjump,
+                    gliding,
+                    wind,
+                    slide,
+                    wall_slide,
+                    ledge_hang
+                ).chain(),
+                run_state_hooks
+
+            ).chain().run_if(playing));
+    }
+}
 
 #[derive(Component)]
 pub struct Swing {
@@ -88,97 +60,75 @@ fn walk(
         Entity, 
         &mut PlayerDirection, 
         &mut Transform, 
-        Has<Grounded>,
-        Has<Crouch>,
-        Has<SideflipL>,
-        Has<SideflipR>,
-        Has<Longjump>,
-        Has<Strafe>, 
-        Has<Slide>,
-        &Swing,
+        &mut PlayerState,
+        &GravityDirection
     ), With<CharacterController>>,
     camera: Query<&Transform, (Without<CharacterController>, With<CameraIdentifier>)>,
-    gamepads: Res<Gamepads>,
-    axes: Res<Axis<GamepadAxis>>,
-    mut commands: Commands,
-    mut movement_event: EventWriter<MovementAction>
+    intent: Query<&PlayerIntent, With<CharacterController>>,
+    mut movement_event: EventWriter<MovementAction>,
+    mut state_changed: EventWriter<StateChanged>,
+    tuning: Res<MovementTuning>,
+    time: Res<Time>
 ) {
-    let Ok((player, mut direction, mut transform, grounded, crouching, sideflip_l, sideflip_r, longjump, strafe, slide, swing)) = player.get_single_mut() else {return;};
-    if sideflip_l || sideflip_r || longjump || slide || swing.swing_number > 0 || strafe { return; }
-    
+    let Ok((player, mut direction, mut transform, mut state, gravity_direction)) = player.get_single_mut() else {return;};
+    let Ok(intent) = intent.get_single() else {return;};
+    if matches!(
+        *state,
+        PlayerState::SideflipL | PlayerState::SideflipR | PlayerState::Longjump | PlayerState::Slide | PlayerState::Swing
+        | PlayerState::WallSlide | PlayerState::LedgeHang | PlayerState::Grind | PlayerState::Swingshot | PlayerState::Glide
+        | PlayerState::Swim | PlayerState::Dive | PlayerState::Climb
+    ) { return; }
+
+    // strafing takes over the walk
+    if intent.strafe && state.allows_strafe() { return; }
+
 
     let Ok(camera_transform) = camera.get_single() else {return;};
 
-   
+    let crouching = *state == PlayerState::Crouch;
 
-    for gamepad in gamepads.iter() {
-        let axis_rx = GamepadAxis {
-            gamepad,
-            axis_type: GamepadAxisType::LeftStickX
-        };
-    
-        let axis_ry = GamepadAxis {
-            gamepad,
-            axis_type: GamepadAxisType::LeftStickY
-        };
+    // the stick comes in already past its deadzone, so anything but zero is walking
+    if let Some(controller_axes) = intent.movement.try_normalize() {
 
+        // calculates the angle to slerp the player to
 
+        let controller_angle = get_angle(controller_axes.x, controller_axes.y);
 
-        if let(Some(x), Some(y)) = (axes.get(axis_rx), axes.get(axis_ry)) {
-            
-            if x > 0.2 || x < -0.2 && !x.is_nan()
-            || y > 0.2 || y < -0.2 && !y.is_nan() {
-                
-                // calculates the angle to slerp the player to
-                let controller_axes = Vec2::new(x, y).normalize();
+        let cam_angle = get_camera_angle(camera_transform, &transform, gravity_direction);
 
-                let controller_angle = get_angle(controller_axes.x, controller_axes.y);
+        let target_angle = controller_angle - cam_angle;
 
-                let cam_angle = get_camera_angle(camera_transform, &transform);
 
-                let target_angle = controller_angle - cam_angle;
+        let target_rotation = Quat::from_rotation_y(target_angle);
 
+        if !crouching {
 
-                let target_rotation = Quat::from_rotation_y(target_angle);
+            transform.rotation = transform.rotation.slerp(target_rotation, 1. - decay(tuning.turn_rate, &time));
 
-                if !crouching {
+            direction.0 = Vec2::new(
+                transform.forward().x, 
+                transform.forward().z
+            ).normalize();
+        }
+        else {
+            transform.rotation = transform.rotation.slerp(target_rotation, 1. - decay(tuning.crouch_turn_rate, &time));
 
-                    transform.rotation = transform.rotation.slerp(target_rotation, 0.07);
-                        
-                    direction.0 = Vec2::new(
-                        transform.forward().x, 
-                        transform.forward().z
-                    ).normalize();
-                }
-                else {
-                    transform.rotation = transform.rotation.slerp(target_rotation, 0.007);
-                    
-                }
-                
+        }
 
-                commands.entity(player).remove::<Land>();
-                commands.entity(player).insert(Walk);
+        if matches!(*state, PlayerState::Idle | PlayerState::Land) {
+            change_state(player, &mut state, PlayerState::Walk, &mut state_changed);
+        }
 
-                if grounded {
-                    commands.entity(player).remove::<DoubleJump>();
-                }
-                
-            }
-            else {
-                direction.0 = Vec2::new(
-                    0.,
-                    0.
-                );
-                commands.entity(player).insert(Idle);
-                commands.entity(player).remove::<Walk>();
-
-                if grounded {
-                    commands.entity(player).remove::<DoubleJump>();
-                }
-                
-            }
+    }
+    else {
+        direction.0 = Vec2::new(
+            0.,
+            0.
+        );
+
+        if *state == PlayerState::Walk {
+            change_state(player, &mut state, PlayerState::Idle, &mut state_changed);
         }
-        
 
     }
 
@@ -186,18 +136,27 @@ fn walk(
         movement_event.send(MovementAction::Walk(direction.0));
 
     }
+    else {
+        // crouch-walking goes where the stick points, the player keeps turning slowly to aim
+        let crawl = stick_direction(intent.movement, camera_transform, &transform, gravity_direction);
+
+        movement_event.send(MovementAction::Walk(crawl * tuning.crouch_walk_factor));
+    }
 
 }
 
 
 
+// the angle around the player the camera is at, on the ground of the player's gravity
 pub fn get_camera_angle(
     camera_transform: &Transform,
-    player_transform: &Transform
+    player_transform: &Transform,
+    gravity_direction: &GravityDirection
 ) -> f32 {
-    let mut cam_angle = camera_transform.rotation.angle_between(Quat::from_rotation_y(0.));
-    
-    if camera_transform.translation.x > player_transform.translation.x {
+    let rotation = gravity_direction.frame().inverse() * camera_transform.rotation;
+    let mut cam_angle = rotation.angle_between(Quat::from_rotation_y(0.));
+
+    if gravity_direction.heading(camera_transform.translation - player_transform.translation).x > 0. {
         cam_angle *= -1.;
     }
 
@@ -210,95 +169,56 @@ fn strafe(
         Entity, 
         &mut PlayerDirection, 
         &mut Transform, 
-        Has<Grounded>,
-        Has<Crouch>,
-        Has<SideflipL>,
-        Has<SideflipR>,
-        Has<Highjump>,
-        Has<Longjump>,
-        Has<Glide>,
-        &Swing,
-        Has<Slide>
+        &mut PlayerState,
+        &GravityDirection
     ), With<CharacterController>>,
     camera_angle: Query<&Transform, (Without<CharacterController>, With<CameraIdentifier>)>,
-    gamepads: Res<Gamepads>,
-    axes: Res<Axis<GamepadAxis>>,
-    buttons: Res<ButtonInput<GamepadButton>>,
-    mut commands: Commands,
-    mut movement_event: EventWriter<MovementAction>
+    intent: Query<&PlayerIntent, With<CharacterController>>,
+    mut movement_event: EventWriter<MovementAction>,
+    mut state_changed: EventWriter<StateChanged>,
+    tuning: Res<MovementTuning>,
+    time: Res<Time>
 ) {
-    let Ok((player, mut direction, mut transform, grounded, crouching, sideflip_l, sideflip_r, highjump, longjump, glide, swing, slide)) = player.get_single_mut() else {return;};
-    if crouching || longjump || glide || slide || sideflip_l || sideflip_r|| swing.swing_number > 0 {return;}
+    let Ok((player, mut direction, mut transform, mut state, gravity_direction)) = player.get_single_mut() else {return;};
+    let Ok(intent) = intent.get_single() else {return;};
+    if !state.allows_strafe() {return;}
 
-    if highjump || glide {
-        commands.entity(player).remove::<Strafe>();
-        return;
-    }
     let Ok( camera_transform) = camera_angle.get_single() else {return;};
 
-    for gamepad in gamepads.iter() {
-        let axis_rx = GamepadAxis {
-            gamepad,
-            axis_type: GamepadAxisType::LeftStickX
-        };
-    
-        let axis_ry = GamepadAxis {
-            gamepad,
-            axis_type: GamepadAxisType::LeftStickY
-        };
-
-        if let(Some(x), Some(y)) = (axes.get(axis_rx), axes.get(axis_ry)) {
-            
-            let strafe = GamepadButton {
-                gamepad,
-                button_type: GamepadButtonType::LeftTrigger2
-            };
-
-            if !buttons.pressed(strafe) {
-                commands.entity(player).remove::<Strafe>();
-                return;
-            }
-
-
-            
-            let target_rotation = Quat::from_rotation_y(-get_camera_angle(camera_transform, &transform) + 1.5708);
+    if !intent.strafe {
+        if *state == PlayerState::Strafe {
+            change_state(player, &mut state, PlayerState::Idle, &mut state_changed);
+        }
+        return;
+    }
 
-            transform.rotation = transform.rotation.slerp(target_rotation, 0.13);
+    let target_rotation = Quat::from_rotation_y(-get_camera_angle(camera_transform, &transform, gravity_direction) + 1.5708);
 
-            if x != 0. && !x.is_nan()
-            || y != 0. && !y.is_nan() {
+    transform.rotation = transform.rotation.slerp(target_rotation, 1. - decay(tuning.strafe_turn_rate, &time));
 
-                let controller_axes = Vec2::new(x, y).normalize();
+    if let Some(controller_axes) = intent.movement.try_normalize() {
 
-                let controller_angle = get_angle(controller_axes.x, controller_axes.y);
+        let controller_angle = get_angle(controller_axes.x, controller_axes.y);
 
-                let direction_angle = -get_camera_angle(camera_transform, &transform) + controller_angle;
+        let direction_angle = -get_camera_angle(camera_transform, &transform, gravity_direction) + controller_angle;
 
-                direction.0 = Vec2::new(
-                    -direction_angle.sin(), 
-                    -direction_angle.cos(), 
-                    
-                );
+        direction.0 = Vec2::new(
+            -direction_angle.sin(), 
+            -direction_angle.cos(), 
 
+        );
 
-                commands.entity(player).insert(Strafe);
-                commands.entity(player).remove::<Land>();
-                commands.entity(player).insert(Walk);
+        // in the air the player keeps strafing without leaving the jump
+        if state.is_grounded() {
+            change_state(player, &mut state, PlayerState::Strafe, &mut state_changed);
+        }
 
-                if grounded {
-                    commands.entity(player).remove::<DoubleJump>();
-                }
+    }
+    else {
+        direction.0 = Vec2::new(0., 0.);
 
-            }
-            else {
-                direction.0 = Vec2::new(0., 0.);
-                commands.entity(player).insert(Idle);
-                commands.entity(player).remove::<Walk>();
-                
-                if grounded {
-                    commands.entity(player).remove::<DoubleJump>();
-                }
-            }
+        if *state == PlayerState::Strafe {
+            change_state(player, &mut state, PlayerState::Idle, &mut state_changed);
         }
     }
 
@@ -311,29 +231,40 @@ fn strafe(
 fn crouch(
     mut player: Query<(
         Entity, 
-        Has<Grounded>
+        &mut PlayerState,
+        &Transform,
+        &CharacterShape,
+        &mut Headroom,
+        &GravityDirection,
+        &JumpCounter,
+        &CoyoteTime
     ), With<CharacterController>>,
-    gamepads: Res<Gamepads>,
-    buttons: Res<ButtonInput<GamepadButton>>,
-    mut commands: Commands
+    intent: Query<&PlayerIntent, With<CharacterController>>,
+    mut state_changed: EventWriter<StateChanged>,
+    spatial_query: SpatialQuery,
+    time: Res<Time>
 ) {
-    let Ok((player_entity, grounded)) = player.get_single_mut() else {return;};
-    for gamepad in gamepads.iter() {
-        let crouch = GamepadButton {
-            gamepad,
-            button_type: GamepadButtonType::RightTrigger
-        };
-
+    let Ok((player_entity, mut state, transform, shape, mut headroom, gravity_direction, jump_counter, coyote_time)) = player.get_single_mut() else {return;};
+    let Ok(up) = Direction3d::new(gravity_direction.up()) else {return;};
+    let Ok(intent) = intent.get_single() else {return;};
+
+    // standing up the top of the collider goes up twice as much as its center
+    headroom.0 = *state != PlayerState::Crouch || spatial_query.cast_ray(
+        transform.translation,
+        up,
+        shape.half_height + (shape.standing_half_height - shape.half_height) * 2.,
+        true,
+        SpatialQueryFilter::from_excluded_entities([player_entity])
+    ).is_none();
+
+    if intent.crouch && can_crouch(*state, coyote_time, jump_counter, &time) {
+        change_state(player_entity, &mut state, PlayerState::Crouch, &mut state_changed);
 
-        if buttons.pressed(crouch) && grounded{
-            commands.entity(player_entity).insert(Crouch);
-            commands.entity(player_entity).remove::<Land>();
-
-        }
-        else {
-            commands.entity(player_entity).remove::<Crouch>();
+    }
+    // under a low ceiling the player stays down until there's room again
+    else if *state == PlayerState::Crouch && headroom.0 {
+        change_state(player_entity, &mut state, PlayerState::Idle, &mut state_changed);
 
-        }
     }
 
 
@@ -342,185 +273,166 @@ fn crouch(
 
 fn sideflips(
     mut player: Query<(
-        Entity,
-        &mut PlayerDirection,
-        Has<Crouch>, 
+        Entity, 
+        &mut PlayerDirection, 
+        &mut PlayerState,
         &Transform,
-        Has<Strafe>,
-        Has<Grounded>,
         &mut LinearVelocity,
-        &mut JumpCounter
+        &mut JumpCounter,
+        &mut InputBuffer,
+        &CoyoteTime,
+        &Headroom,
+        &GravityDirection
     ), With<CharacterController>>,
     camera_angle: Query<&Transform, With<CameraIdentifier>>,
-    gamepads: Res<Gamepads>,
-    axes: Res<Axis<GamepadAxis>>,
-    buttons: Res<ButtonInput<GamepadButton>>,
+    intent: Query<&PlayerIntent, With<CharacterController>>,
     mut commands: Commands,
     mut movement_event: EventWriter<MovementAction>,
-    asset_server: Res<AssetServer>   
+    mut state_changed: EventWriter<StateChanged>,
+    asset_server: Res<AssetServer>,
+    time: Res<Time>
 
 ) {
-    let Ok((entity, direction, crouching, transform, strafing, grounded, mut velocity, mut jump_counter, )) = player.get_single_mut() else {return;};
+    let Ok((entity, direction, mut state, transform, mut velocity, mut jump_counter, mut input_buffer, coyote_time, headroom, gravity_direction)) = player.get_single_mut() else {return;};
+    let Ok(intent) = intent.get_single() else {return;};
     let Ok(camera_transform) = camera_angle.get_single() else {return;};
- 
-    for gamepad in gamepads.iter() {
-        let axis_rx = GamepadAxis {
-            gamepad,
-            axis_type: GamepadAxisType::LeftStickX
-        };
-    
-        let axis_ry = GamepadAxis {
-            gamepad,
-            axis_type: GamepadAxisType::LeftStickY
-        };
 
+    // there's no room to jump out of a crouch under a low ceiling
+    if *state == PlayerState::Crouch && !headroom.0 {return;}
+
+    let Some(controller_axes) = intent.movement.try_normalize() else {return;};
+
+    // sideflip phisycs chage while strafing
+    if *state == PlayerState::Crouch {
 
+        // non-strafing sideflip
 
-        if let(Some(x), Some(y)) = (axes.get(axis_rx), axes.get(axis_ry)) {
+        let controller_angle = get_angle(controller_axes.x, controller_axes.y).to_degrees();
 
-            if x < 0.2 && x > -0.2 || y < 0.2 && y > -0.2 {return;}
+        let mut player_angle = get_angle(-direction.0.x, direction.0.y).to_degrees() + 90.;
 
-            let controller_axes = Vec2::new(x, y).normalize();
+        let camera_angle = get_camera_angle(camera_transform, &transform, gravity_direction);
 
-            // sideflip phisycs chage while strafing
-            if !strafing {
+        player_angle += camera_angle.to_degrees();
 
-                // non-strafing sideflip
 
-                let controller_angle = get_angle(controller_axes.x, controller_axes.y).to_degrees();
+        // i don't have the slightest idea of why player_angle sometimes skyrockets beyond 720, however this solution should work. 
+        if player_angle > 720. {
+            player_angle -= 720.;
+        }
+        else if player_angle > 360. {
+            player_angle -= 360.;
+        }
 
-                let mut player_angle = get_angle(-direction.0.x, direction.0.y).to_degrees() + 90.;
 
-                let camera_angle = get_camera_angle(camera_transform, &transform);
+        if input_buffer.jump_time.is_some() {
+            if player_angle - controller_angle + camera_angle > 25. 
+            && change_state(entity, &mut state, PlayerState::SideflipR, &mut state_changed) {
+                input_buffer.jump_time = None;
+                movement_event.send(MovementAction::Sideflip(Vec2::new(
+                    transform.right().x, 
+                    transform.right().z
+                )));
 
-                player_angle += camera_angle.to_degrees();
+                jump_counter.counter = 2.;
 
+                commands.spawn((
+                    AudioBundle {
+                        source: asset_server.load("jump2.ogg"),
+                        settings: PlaybackSettings {
+                            volume: Volume::new(0.07),
+                            speed: 0.75,
+                            mode: PlaybackMode::Despawn,
+                            ..default()
+                        } 
+                    },
 
-                // i don't have the slightest idea of why player_angle sometimes skyrockets beyond 720, however this solution should work. 
-                if player_angle > 720. {
-                    player_angle -= 720.;
-                }
-                else if player_angle > 360. {
-                    player_angle -= 360.;
-                }
-                
-
-                let jump = GamepadButton {
-                    gamepad,
-                    button_type: GamepadButtonType::South
-                };
-
-                if crouching && buttons.just_pressed(jump) {
-                    if player_angle - controller_angle + camera_angle > 25. {
-                        commands.entity(entity).insert(SideflipR);
-                        movement_event.send(MovementAction::Sideflip(Vec2::new(
-                            transform.right().x, 
-                            transform.right().z
-                        )));
-
-                        jump_counter.counter = 2.;
-
-                        commands.spawn((
-                            AudioBundle {
-                                source: asset_server.load("jump2.ogg"),
-                                settings: PlaybackSettings {
-                                    volume: Volume::new(0.07),
-                                    speed: 0.75,
-                                    mode: PlaybackMode::Despawn,
-                                    ..default()
-                                }
-                            },
-                            
-                        ));
+                ));
 
-                    }
-                    else if player_angle - controller_angle + camera_angle < -25. {
-                        
-                        
-                        commands.entity(entity).insert(SideflipL);
-                        movement_event.send(MovementAction::Sideflip(Vec2::new(
-                            transform.left().x, 
-                            transform.left().z
-                        )));
-
-                        jump_counter.counter = 2.;
-
-                        commands.spawn((
-                            AudioBundle {
-                                source: asset_server.load("jump2.ogg"),
-                                settings: PlaybackSettings {
-                                    volume: Volume::new(0.07),
-                                    speed: 0.75,
-                                    mode: PlaybackMode::Despawn,
-                                    ..default()
-                                }
-                            },
-                            
-                        ));
-                    }
-                }
             }
-            else {
-
-                // strafing sideflip 
-
-                let jump = GamepadButton {
-                    gamepad,
-                    button_type: GamepadButtonType::South
-                };
-
-                if buttons.just_pressed(jump) {
-                    if controller_axes.normalize().x > 0.4226 && grounded {
-                        velocity.x = 0.;
-                        velocity.z = 0.;
-                        commands.entity(entity).insert(SideflipR);
-                        movement_event.send(MovementAction::Sideflip(Vec2::new(
-                            transform.right().x, 
-                            transform.right().z
-                        )));
-
-                        jump_counter.counter = 2.;
-
-                        commands.spawn((
-                            AudioBundle {
-                                source: asset_server.load("jump2.ogg"),
-                                settings: PlaybackSettings {
-                                    volume: Volume::new(0.07),
-                                    speed: 0.75,
-                                    mode: PlaybackMode::Despawn,
-                                    ..default()
-                                }
-                            },
-                        ));
-
-                    }
-                    else if controller_axes.normalize().x < -0.4226 && grounded {
-                        velocity.x = 0.;
-                        velocity.z = 0.;
-                        commands.entity(entity).insert(SideflipL);
-                        movement_event.send(MovementAction::Sideflip(Vec2::new(
-                            transform.left().x, 
-                            transform.left().z
-                        ).normalize()));
-
-                        jump_counter.counter = 2.;
-
-                        commands.spawn((
-                            AudioBundle {
-                                source: asset_server.load("jump2.ogg"),
-                                settings: PlaybackSettings {
-                                    volume: Volume::new(0.07),
-                                    speed: 0.75,
-                                    mode: PlaybackMode::Despawn,
-                                    ..default()
-                                }
-                            },
-                        ));
+            else if player_angle - controller_angle + camera_angle < -25. 
+            && change_state(entity, &mut state, PlayerState::SideflipL, &mut state_changed) {
+
+                input_buffer.jump_time = None;
+                movement_event.send(MovementAction::Sideflip(Vec2::new(
+                    transform.left().x, 
+                    transform.left().z
+                )));
+
+                jump_counter.counter = 2.;
+
+                commands.spawn((
+                    AudioBundle {
+                        source: asset_server.load("jump2.ogg"),
+                        settings: PlaybackSettings {
+                            volume: Volume::new(0.07),
+                            speed: 0.75,
+                            mode: PlaybackMode::Despawn,
+                            ..default()
+                        } 
+                    },
+
+                ));
+            }
+        }
+    }
+    else if intent.strafe {
+
+        // strafing sideflip 
+
+        let grounded = state.is_grounded() || in_coyote_time(coyote_time, &jump_counter, &time);
+
+        if input_buffer.jump_time.is_some() {
+            if controller_axes.x > 0.4226 && grounded 
+            && change_state(entity, &mut state, PlayerState::SideflipR, &mut state_changed) {
+                input_buffer.jump_time = None;
+                let horizontal = gravity_direction.flatten(velocity.0);
+                velocity.0 -= horizontal;
+                movement_event.send(MovementAction::Sideflip(Vec2::new(
+                    transform.right().x, 
+                    transform.right().z
+                )));
+
+                jump_counter.counter = 2.;
+
+                commands.spawn((
+                    AudioBundle {
+                        source: asset_server.load("jump2.ogg"),
+                        settings: PlaybackSettings {
+                            volume: Volume::new(0.07),
+                            speed: 0.75,
+                            mode: PlaybackMode::Despawn,
+                            ..default()
+                        } 
+                    },
+                ));
 
-                    }
-                }
             }
+            else if controller_axes.x < -0.4226 && grounded 
+            && change_state(entity, &mut state, PlayerState::SideflipL, &mut state_changed) {
+                input_buffer.jump_time = None;
+                let horizontal = gravity_direction.flatten(velocity.0);
+                velocity.0 -= horizontal;
+                movement_event.send(MovementAction::Sideflip(Vec2::new(
+                    transform.left().x, 
+                    transform.left().z
+                ).normalize()));
+
+                jump_counter.counter = 2.;
+
+                commands.spawn((
+                    AudioBundle {
+                        source: asset_server.load("jump2.ogg"),
+                        settings: PlaybackSettings {
+                            volume: Volume::new(0.07),
+                            speed: 0.75,
+                            mode: PlaybackMode::Despawn,
+                            ..default()
+                        } 
+                    },
+                ));
 
+            }
         }
     }
 }
@@ -528,112 +440,90 @@ fn sideflips(
 
 fn longjump(
     mut player: Query<(
-        Entity,
-        &mut PlayerDirection,
-        Has<Crouch>, 
+        Entity, 
+        &mut PlayerDirection, 
+        &mut PlayerState,
         &Transform,
-        Has<Longjump>,
-        Has<Highjump>,
         &LinearVelocity,
         &mut JumpCounter,
-        &mut GravityScale
+        &mut InputBuffer,
+        &Headroom
     ), With<CharacterController>>,
     camera_angle: Query<&CameraIdentifier>,
-    gamepads: Res<Gamepads>,
-    axes: Res<Axis<GamepadAxis>>,
-    buttons: Res<ButtonInput<GamepadButton>>,
+    intent: Query<&PlayerIntent, With<CharacterController>>,
     mut commands: Commands,
     mut movement_event: EventWriter<MovementAction>,
+    mut state_changed: EventWriter<StateChanged>,
     asset_server: Res<AssetServer> ,
+    tuning: Res<MovementTuning>,
     time: Res<Time>  
 
 ) {
-    let Ok((entity, mut direction, crouching, transform, longjumping, highjumping,velocity, mut jump_counter, mut gravity_scale)) = player.get_single_mut() else {return;};
-    if highjumping {return;}
-
-    let Ok(camera_angle) = camera_angle.get_single() else {return;};
- 
-    for gamepad in gamepads.iter() {
-        let axis_rx = GamepadAxis {
-            gamepad,
-            axis_type: GamepadAxisType::LeftStickX
-        };
-    
-        let axis_ry = GamepadAxis {
-            gamepad,
-            axis_type: GamepadAxisType::LeftStickY
-        };
-
+    let Ok((entity, mut direction, mut state, transform, velocity, mut jump_counter, mut input_buffer, headroom)) = player.get_single_mut() else {return;};
+    let Ok(intent) = intent.get_single() else {return;};
+    if *state == PlayerState::Highjump {return;}
 
+    // there's no room to jump out of a crouch under a low ceiling
+    if *state == PlayerState::Crouch && !headroom.0 {return;}
 
-        if let(Some(x), Some(y)) = (axes.get(axis_rx), axes.get(axis_ry)) {
-
-            let controller_axes = Vec2::new(x, y).normalize();
+    let Ok(camera_angle) = camera_angle.get_single() else {return;};
 
-            let controller_angle = get_angle(controller_axes.x, controller_axes.y).to_degrees();
+    // a centered stick has no angle, it's treated as pushing forward
+    let controller_axes = intent.movement.try_normalize().unwrap_or(Vec2::Y);
 
-            let mut player_angle = get_angle(-direction.0.x, direction.0.y).to_degrees() + 90.;
+    let controller_angle = get_angle(controller_axes.x, controller_axes.y).to_degrees();
 
-            player_angle += camera_angle.0.to_degrees();
+    let mut player_angle = get_angle(-direction.0.x, direction.0.y).to_degrees() + 90.;
 
+    player_angle += camera_angle.0.to_degrees();
 
-            // i don't have the slightest idea of why player_angle sometimes skyrockets beyond 720, however this solution should work. 
-            if player_angle > 720. {
-                player_angle -= 720.;
-            }
-            else if player_angle > 360. {
-                player_angle -= 360.;
-            }
-            
 
-            let jump = GamepadButton {
-                gamepad,
-                button_type: GamepadButtonType::South
-            };
+    // i don't have the slightest idea of why player_angle sometimes skyrockets beyond 720, however this solution should work. 
+    if player_angle > 720. {
+        player_angle -= 720.;
+    }
+    else if player_angle > 360. {
+        player_angle -= 360.;
+    }
 
 
 
 
-            if crouching && buttons.just_pressed(jump) && velocity.length() > 2. {
-                if player_angle - controller_angle + camera_angle.0 < 25. 
-                && player_angle - controller_angle + camera_angle.0 > -25. {
+    if *state == PlayerState::Crouch && input_buffer.jump_time.is_some() && velocity.length() > 2. {
+        if player_angle - controller_angle + camera_angle.0 < 25. 
+        && player_angle - controller_angle + camera_angle.0 > -25. 
+        && change_state(entity, &mut state, PlayerState::Longjump, &mut state_changed) {
 
-                    commands.entity(entity).insert(Longjump);
+            input_buffer.jump_time = None;
 
-                    movement_event.send(MovementAction::Longjump(direction.0));                        
+            movement_event.send(MovementAction::Longjump(direction.0));                        
 
 
-                    jump_counter.counter = 2.;
-                    jump_counter.jump_time = time.elapsed_seconds();
+            jump_counter.counter = 2.;
+            jump_counter.jump_time = time.elapsed_seconds();
 
-                    commands.spawn((
-                        AudioBundle {
-                            source: asset_server.load("glide.ogg"),
-                            settings: PlaybackSettings {
-                                volume: Volume::new(0.07),
-                                speed: 0.75,
-                                mode: PlaybackMode::Despawn,
-                                ..default()
-                            }
-                        },
-                    ));
+            commands.spawn((
+                AudioBundle {
+                    source: asset_server.load("glide.ogg"),
+                    settings: PlaybackSettings {
+                        volume: Volume::new(0.07),
+                        speed: 0.75,
+                        mode: PlaybackMode::Despawn,
+                        ..default()
+                    }
+                },
+            ));
 
-                }
-                
-            }
+        }
 
+    }
 
-            if longjumping {
-                direction.0 = Vec2::new(transform.forward().x, transform.forward().z);
 
-                if time.elapsed_seconds() > jump_counter.jump_time + 1.65 {
-                    commands.entity(entity).remove::<Longjump>();
+    if *state == PlayerState::Longjump {
+        direction.0 = Vec2::new(transform.forward().x, transform.forward().z);
 
-                }
-            }
-            else {
-                gravity_scale.0 = 3.;
-            }
+        if time.elapsed_seconds() > jump_counter.jump_time + tuning.longjump_time {
+            change_state(entity, &mut state, PlayerState::Fall, &mut state_changed);
 
         }
     }
@@ -641,304 +531,324 @@ fn longjump(
 
 fn highjump(
     mut player: Query<(
-        Entity,
-        Has<Crouch>, 
-        Has<SideflipL>, 
-        Has<SideflipR>, 
-        Has<Highjump>,
-        Has<Longjump>,
+        Entity, 
+        &mut PlayerState,
         &LinearVelocity,
         &mut JumpCounter,
-        &mut GravityScale
+        &mut GravityScale,
+        &mut InputBuffer,
+        &Headroom
     ), With<CharacterController>>,
-    gamepads: Res<Gamepads>,
-    buttons: Res<ButtonInput<GamepadButton>>,
     mut commands: Commands,
     mut movement_event: EventWriter<MovementAction>,
+    mut state_changed: EventWriter<StateChanged>,
     asset_server: Res<AssetServer> ,
+    tuning: Res<MovementTuning>,
     time: Res<Time>  
 
 ) {
-    let Ok((entity, crouching, sideflip_l, sideflip_r, highjumping, longjumping, velocity, mut jump_counter, mut gravity_scale)) = player.get_single_mut() else {return;};
-    if longjumping || sideflip_l || sideflip_r {return;}
+    let Ok((entity, mut state, velocity, mut jump_counter, mut gravity_scale, mut input_buffer, headroom)) = player.get_single_mut() else {return;};
 
- 
-    for gamepad in gamepads.iter() {
-
-        let jump = GamepadButton {
-            gamepad,
-            button_type: GamepadButtonType::South
-        };
+    // there's no room to jump out of a crouch under a low ceiling
+    if *state == PlayerState::Crouch && !headroom.0 {return;}
 
 
-        if crouching && buttons.just_pressed(jump) && velocity.length() < 2. {
-        
-            commands.entity(entity).insert(Highjump);
+    if *state == PlayerState::Crouch 
+    && input_buffer.jump_time.is_some() 
+    && velocity.length() < 2. 
+    && change_state(entity, &mut state, PlayerState::Highjump, &mut state_changed) {
 
-            movement_event.send(MovementAction::Highjump1);                        
+        input_buffer.jump_time = None;
 
+        movement_event.send(MovementAction::Highjump1);                        
 
-            jump_counter.counter = 2.;
-            jump_counter.jump_time = time.elapsed_seconds();
 
-            commands.spawn((
-                AudioBundle {
-                    source: asset_server.load("glide.ogg"),
-                    settings: PlaybackSettings {
-                        volume: Volume::new(0.07),
-                        speed: 0.75,
-                        mode: PlaybackMode::Despawn,
-                        ..default()
-                    }
-                },
-            ));
+        jump_counter.counter = 2.;
+        jump_counter.jump_time = time.elapsed_seconds();
 
-                
-        }
+        commands.spawn((
+            AudioBundle {
+                source: asset_server.load("glide.ogg"),
+                settings: PlaybackSettings {
+                    volume: Volume::new(0.07),
+                    speed: 0.75,
+                    mode: PlaybackMode::Despawn,
+                    ..default()
+                }
+            },
+        ));
 
-        if highjumping {
 
-            if time.elapsed_seconds() > jump_counter.jump_time + 1.42 {
-                commands.entity(entity).remove::<Highjump>();
+    }
 
+    if *state == PlayerState::Highjump {
 
-            }
-            else if time.elapsed_seconds() > jump_counter.jump_time + 0.7 {
-                movement_event.send(MovementAction::Highjump2);
-                gravity_scale.0 = 1.5;
+        if time.elapsed_seconds() > jump_counter.jump_time + tuning.highjump_time {
+            change_state(entity, &mut state, PlayerState::Fall, &mut state_changed);
 
-            }
 
         }
-        else {
-            gravity_scale.0 = 3.;
+        else if time.elapsed_seconds() > jump_counter.jump_time + tuning.highjump_second_time {
+            movement_event.send(MovementAction::Highjump2);
+            gravity_scale.0 = tuning.highjump_second_gravity_scale;
+
         }
+
     }
 }
 
 fn jump(
-    gamepads: Res<Gamepads>,
-    buttons: Res<ButtonInput<GamepadButton>>,
-    mut player: Query<(Entity, &JumpCounter, Has<Grounded>, Has<Crouch>), With<CharacterController>>,
+    mut player: Query<(Entity, &JumpCounter, &mut PlayerState, &mut InputBuffer, &CoyoteTime), With<CharacterController>>,
     mut movement_event: EventWriter<MovementAction>,
+    mut state_changed: EventWriter<StateChanged>,
     mut commands: Commands,
-    
+    time: Res<Time>,
+
     asset_server: Res<AssetServer>   
 
 ) {
-    let Ok((player, jump_counter, grounded, crouch,)) = player.get_single_mut() else {return;};
-
-    if crouch {return;};
-
-    for gamepad in gamepads.iter() {
-        let jump = GamepadButton {
-            gamepad,
-            button_type: GamepadButtonType::South
-        };
-
-
-        if buttons.just_pressed(jump) && jump_counter.counter < 1. && grounded {
-            commands.entity(player).insert(Jump);
-            commands.entity(player).remove::<Walk>();
-            movement_event.send(MovementAction::Jump);
-
+    let Ok((player, jump_counter, mut state, mut input_buffer, coyote_time)) = player.get_single_mut() else {return;};
+
+    // crouching turns the jump into a sideflip, a longjump or a highjump
+    if *state == PlayerState::Crouch {return;};
+
+    if input_buffer.jump_time.is_some()
+    && jump_counter.counter < 1.
+    && (state.is_grounded() || in_coyote_time(coyote_time, jump_counter, &time))
+    && change_state(player, &mut state, PlayerState::Jump, &mut state_changed) {
+        input_buffer.jump_time = None;
+        movement_event.send(MovementAction::Jump);
+
+
+        // spawns an audio player that plays the jump sound
+        commands.spawn((
+            AudioBundle {
+                source: asset_server.load("jump.ogg"),
+                settings: PlaybackSettings {
+                    volume: Volume::new(0.07),
+                    speed: 0.75,
+                    mode: PlaybackMode::Despawn,
+                    ..default()
+                }
+            },
 
-            // spawns an audio player that plays the jump sound
-            commands.spawn((
-                AudioBundle {
-                    source: asset_server.load("jump.ogg"),
-                    settings: PlaybackSettings {
-                        volume: Volume::new(0.07),
-                        speed: 0.75,
-                        mode: PlaybackMode::Despawn,
-                        ..default()
-                    }
-                },
-                
-            ));
+        ));
 
-        }
     }
 
 }
 
 fn doublejump(
-    gamepads: Res<Gamepads>,
-    buttons: Res<ButtonInput<GamepadButton>>,
-    mut player: Query<(Entity, &JumpCounter), With<CharacterController>>,
+    mut player: Query<(Entity, &JumpCounter, &mut PlayerState, &mut InputBuffer), With<CharacterController>>,
     mut movement_event: EventWriter<MovementAction>,
+    mut state_changed: EventWriter<StateChanged>,
     mut commands: Commands,
+    tuning: Res<MovementTuning>,
     time: Res<Time>,
 
     asset_server: Res<AssetServer>   
 
 ) {
-    let Ok((player, jump_counter)) = player.get_single_mut() else {return;};
-
-    for gamepad in gamepads.iter() {
-        let jump = GamepadButton {
-            gamepad,
-            button_type: GamepadButtonType::South
-        };
-
-
-        if buttons.just_pressed(jump) 
-        && jump_counter.counter < 2.
-        && jump_counter.counter > 0.
-        && jump_counter.jump_time + 0.65 > time.elapsed_seconds() {
-            commands.entity(player).remove::<Jump>();
-            commands.entity(player).insert(DoubleJump);
-
-            movement_event.send(MovementAction::DoubleJump);
-
-            // spawns an audio player that plays the doublejump sound
-            commands.spawn((
-                AudioBundle {
-                    source: asset_server.load("jump2.ogg"),
-                    settings: PlaybackSettings {
-                        volume: Volume::new(0.07),
-                        speed: 0.75,
-                        mode: PlaybackMode::Despawn,
-                        ..default()
-                    }
-                },
-            ));
+    let Ok((player, jump_counter, mut state, mut input_buffer)) = player.get_single_mut() else {return;};
+
+    if input_buffer.jump_time.is_some()
+    && jump_counter.counter < 2.
+    && jump_counter.counter > 0.
+    && jump_counter.jump_time + tuning.double_jump_window > time.elapsed_seconds()
+    && change_state(player, &mut state, PlayerState::DoubleJump, &mut state_changed) {
+        input_buffer.jump_time = None;
+
+        movement_event.send(MovementAction::DoubleJump);
+
+        // spawns an audio player that plays the doublejump sound
+        commands.spawn((
+            AudioBundle {
+                source: asset_server.load("jump2.ogg"),
+                settings: PlaybackSettings {
+                    volume: Volume::new(0.07),
+                    speed: 0.75,
+                    mode: PlaybackMode::Despawn,
+                    ..default()
+                }
+            },
+        ));
 
-        }
     }
 
 }
 
 fn gliding(
-    gamepads: Res<Gamepads>,
-    buttons: Res<ButtonInput<GamepadButton>>,
-    mut player: Query<(
-        Entity, 
-        Has<Jump>, 
-        Has<DoubleJump>, 
-        Has<Glide>, 
-        Has<Grounded>,
-        Has<SideflipL>,
-        Has<SideflipR>,
-        Has<Longjump>,
-        Has<Highjump>
-    ), With<CharacterController>>,
+    intent: Query<&PlayerIntent, With<CharacterController>>,
+    mut player: Query<(Entity, &mut PlayerState, &mut Transform, &LinearVelocity, &GravityDirection), With<CharacterController>>,
+    camera: Query<&Transform, (Without<CharacterController>, With<CameraIdentifier>)>,
     mut movement_event: EventWriter<MovementAction>,
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    glide_audio: Query<Entity, With<GlideAudio>>,
-
-
+    mut state_changed: EventWriter<StateChanged>,
+    tuning: Res<MovementTuning>,
+    time: Res<Time>
 ) {
-    let Ok((player, jumping, doublejumping, gliding, grounded, sideflip_l, sideflip_r, longjumping, highjumping)) = player.get_single_mut() else {return;};
-    for gamepad in gamepads.iter() {
-        let glide = GamepadButton {
-            gamepad,
-            button_type: GamepadButtonType::South
-        };
+    let Ok((player, mut state, mut transform, velocity, gravity_direction)) = player.get_single_mut() else {return;};
+    let Ok(intent) = intent.get_single() else {return;};
+    let Ok(camera_transform) = camera.get_single() else {return;};
 
-        if !jumping && !doublejumping && !sideflip_l && !sideflip_r && !grounded && !longjumping && !highjumping {
-            if buttons.pressed(glide)  {
-                commands.entity(player).insert(Glide);
-                movement_event.send(MovementAction::Gliding);
-
-                // if ratchet just started gliding it plays the glide audio
-                if !gliding {
-                    commands.spawn((
-                        AudioBundle {
-                            source: asset_server.load("glide.ogg"),
-                            settings: PlaybackSettings {
-                                volume: Volume::new(0.07),
-                                speed: 0.75,
-                                mode: PlaybackMode::Loop,
-                                ..default()
-                            }
-                        },
-                        GlideAudio
-                    ));
+    // the glide audio starts and stops with the glide state
+    if intent.jump_held {
+        let was_gliding = *state == PlayerState::Glide;
 
-                }
-            }
-            else {
-                commands.entity(player).remove::<Glide>();
+        if matches!(*state, PlayerState::Fall | PlayerState::Glide)
+        && change_state(player, &mut state, PlayerState::Glide, &mut state_changed) {
+            // the glide starts heading where the player was going
+            let moving = gravity_direction.heading(velocity.0);
+            if !was_gliding && moving.length() > 0.5 {
+                transform.rotation = Quat::from_rotation_y(f32::atan2(-moving.x, -moving.y));
             }
-        }
-        
 
-        if !gliding {
-            for audio in glide_audio.iter() {
-                commands.entity(audio).despawn();
+            // and the stick steers it
+            let stick = stick_direction(intent.movement, camera_transform, &transform, gravity_direction);
+            if stick != Vec2::ZERO {
+                let target_rotation = Quat::from_rotation_y(f32::atan2(-stick.x, -stick.y));
+                transform.rotation = transform.rotation.slerp(target_rotation, 1. - decay(tuning.glide_turn_rate, &time));
             }
+
+            let facing = Vec2::new(transform.forward().x, transform.forward().z).normalize_or_zero();
+
+            movement_event.send(MovementAction::Gliding(facing, intent.movement.length().min(1.)));
         }
     }
+    else if *state == PlayerState::Glide {
+        change_state(player, &mut state, PlayerState::Fall, &mut state_changed);
+    }
 }
 
 
-fn update_grounded(
-    mut commands: Commands,
+pub fn update_grounded(
+    mut state_changed: EventWriter<StateChanged>,
     mut query: Query<(
             Entity, 
             &ShapeHits, 
             &Rotation, 
             Option<&MaxSlopeAngle>,
-            Has<Falling>,
-            &mut JumpCounter,
+            &mut PlayerState,
+            &mut LinearVelocity,
             &mut GroundedHeight,
-            &Transform
+            &mut CoyoteTime,
+            &mut GroundNormal,
+            &mut GroundVelocity,
+            &GroundSnapDistance,
+            &CharacterShape,
+            &GravityDirection,
+            &mut Transform
         ),
         With<CharacterController>,
     >,
-
+    spatial_query: SpatialQuery,
+    time: Res<Time>,
 
 
 ) {
-    let Ok((entity, hits, rotation, max_slope_angle, is_falling, mut jump_counter, mut grounded_height, transform)) = query.get_single_mut() else {return;};
-
+    let Ok((
+        entity,
+        hits,
+        rotation,
+        max_slope_angle,
+        mut state,
+        mut velocity,
+        mut grounded_height,
+        mut coyote_time,
+        mut ground_normal,
+        mut ground_velocity,
+        snap_distance,
+        shape,
+        gravity_direction,
+        mut transform
+    )) = query.get_single_mut() else {return;};
+
+    // slopes, landings and snapping are all measured against the way gravity pulls
+    let up = gravity_direction.up();
+
+    // the rail, the water or the wall decide where the player is, whatever is under it
+    if matches!(*state, PlayerState::Grind | PlayerState::Climb) || state.is_swimming() {
+        ground_velocity.entity = None;
+        return;
+    }
 
     // if the ground check detects a hit it checks the slope angle of the mesh it has just hit
-    // if it's too steep then the character will not be grounded
-    let is_grounded = hits.iter().any(|hit| {
-        if let Some(angle) = max_slope_angle {
-            rotation.rotate(-hit.normal2).angle_between(Vector::Y).abs() <= angle.0
-        } else {
-            true
+    // if it's too steep then the character will not be grounded, it slides down instead
+    let mut walkable_normal = None;
+    let mut steep_normal = None;
+
+    for hit in hits.iter() {
+        let normal = rotation.rotate(-hit.normal2);
+
+        let too_steep = max_slope_angle.is_some_and(|angle| normal.angle_between(up).abs() > angle.0);
+
+        if too_steep {
+            steep_normal.get_or_insert(normal);
+        }
+        else {
+            walkable_normal.get_or_insert((normal, hit.entity));
         }
-    });
+    }
 
-    if is_grounded {
-        commands.entity(entity).remove::<Falling>();
-        commands.entity(entity).remove::<Glide>();
-        commands.entity(entity).remove::<Slide>();
+    ground_velocity.entity = walkable_normal.map(|(_, ground)| ground);
 
-        grounded_height.0 = transform.translation.y;
+    if let Some((normal, _)) = walkable_normal {
+        ground_normal.0 = normal;
+        grounded_height.0 = gravity_direction.height(transform.translation);
+        coyote_time.grounded_time = time.elapsed_seconds();
 
-        commands.entity(entity).insert(Grounded);
+        // if ratchet was in the air and is now coming down on the ground he just landed,
+        // right after a jump he's still touching the ground while going up
+        if !state.is_grounded() && velocity.dot(up) <= 0. {
+            change_state(entity, &mut state, PlayerState::Land, &mut state_changed);
 
-        // if ratchet was falling and now he's grounded he just landed
-        if is_falling {
-            commands.entity(entity).remove::<SideflipL>();
-            commands.entity(entity).remove::<SideflipR>();
-            commands.entity(entity).remove::<Jump>();
+        }
 
-            commands.entity(entity).insert(Land);
-            commands.entity(entity).remove::<Longjump>();
 
 
-            jump_counter.counter = 0.;
 
-        }
-        
+    }
+    else if let Some(normal) = steep_normal {
+        ground_normal.0 = normal;
 
-        
-        
+        // jumping off a slope doesn't slide again until the player comes back down
+        if velocity.dot(up) <= 0. {
+            change_state(entity, &mut state, PlayerState::Slide, &mut state_changed);
+        }
     }
-    else {
-        commands.entity(entity).remove::<Grounded>();
-        commands.entity(entity).insert(Falling);
+    else if state.is_grounded() || *state == PlayerState::Slide {
+        // walking down stairs or over the top of a slope the ground drops away for a moment,
+        // the player is pulled back down to it instead of falling
+        let snap = if state.is_grounded() {
+            find_ground_below(
+                &spatial_query,
+                entity,
+                transform.translation,
+                gravity_direction.down,
+                shape,
+                snap_distance.0,
+                max_slope_angle.map_or(PI, |angle| angle.0)
+            )
+        }
+        else {
+            None
+        };
+
+        if let Some((normal, ground, distance)) = snap {
+            transform.translation -= up * distance;
+            velocity.0 -= up * velocity.dot(up).max(0.);
+
+            ground_normal.0 = normal;
+            ground_velocity.entity = Some(ground);
+            grounded_height.0 = gravity_direction.height(transform.translation);
+            coyote_time.grounded_time = time.elapsed_seconds();
+        }
+        else {
+            ground_normal.0 = up;
+            change_state(entity, &mut state, PlayerState::Fall, &mut state_changed);
+        }
     }
-    
-}
 
+}
 
 
 
@@ -948,16 +858,941 @@ fn update_grounded(
 
 
 
-fn get_angle(cos: f32, sin: f32) -> f32 {
-    let mut angle = cos.acos();
 
-    if sin < 0. {
+// on steep ground the player slides downhill, steers a little and can jump off
+fn slide(
+    mut player: Query<(
+        Entity,
+        &mut PlayerState,
+        &GroundNormal,
+        &mut InputBuffer,
+        &mut PlayerDirection,
+        &mut Transform,
+        &GravityDirection
+    ), With<CharacterController>>,
+    camera: Query<&Transform, (Without<CharacterController>, With<CameraIdentifier>)>,
+    intent: Query<&PlayerIntent, With<CharacterController>>,
+    mut movement_event: EventWriter<MovementAction>,
+    mut state_changed: EventWriter<StateChanged>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    tuning: Res<MovementTuning>,
+    time: Res<Time>
+) {
+    let Ok((player, mut state, ground_normal, mut input_buffer, mut direction, mut transform, gravity_direction)) = player.get_single_mut() else {return;};
+    let Ok(intent) = intent.get_single() else {return;};
+    let Ok(camera_transform) = camera.get_single() else {return;};
+
+    if *state != PlayerState::Slide {return;}
+
+    if input_buffer.jump_time.is_some()
+    && change_state(player, &mut state, PlayerState::Jump, &mut state_changed) {
+        input_buffer.jump_time = None;
+        movement_event.send(MovementAction::SlideJump(ground_normal.0));
+
+        commands.spawn((
+            AudioBundle {
+                source: asset_server.load("jump.ogg"),
+                settings: PlaybackSettings {
+                    volume: Volume::new(0.07),
+                    speed: 0.75,
+                    mode: PlaybackMode::Despawn,
+                    ..default()
+                }
+            },
+        ));
+
+        return;
+    }
+
+    // the stick steers in the same directions it walks
+    let steering = stick_direction(intent.movement, camera_transform, &transform, gravity_direction);
+
+    // ratchet faces downhill
+    let downhill = gravity_direction.heading(ground_normal.0);
+
+    if let Some(downhill) = downhill.try_normalize() {
+        let target_rotation = Quat::from_rotation_y(f32::atan2(-downhill.x, -downhill.y));
+
+        transform.rotation = transform.rotation.slerp(target_rotation, 1. - decay(tuning.turn_rate, &time));
+        direction.0 = downhill;
+    }
+
+    movement_event.send(MovementAction::Slide(ground_normal.0, steering));
+}
+
+// walkable ground under the player within the snap distance, returns its normal,
+// the entity it belongs to and how far down the player has to move to stand on it
+fn find_ground_below(
+    spatial_query: &SpatialQuery,
+    player: Entity,
+    position: Vec3,
+    down: Vec3,
+    shape: &CharacterShape,
+    snap_distance: f32,
+    max_slope_angle: f32
+) -> Option<(Vec3, Entity, f32)> {
+    // past the reach of the ground caster
+    let max_distance = shape.half_height + 0.2 + snap_distance;
+
+    let hit = spatial_query.cast_ray(
+        position,
+        Direction3d::new(down).ok()?,
+        max_distance,
+        true,
+        SpatialQueryFilter::from_excluded_entities([player])
+    )?;
+
+    if hit.normal.angle_between(-down) > max_slope_angle {return None;}
+
+    // on a slope the round bottom of the capsule touches the ground before its center does
+    let standing_distance = shape.half_height - shape.radius + shape.radius / hit.normal.dot(-down);
+
+    Some((hit.normal, hit.entity, (hit.time_of_impact - standing_distance).max(0.)))
+}
+
+// moves the player along with the body it's standing on and turns it with it,
+// when the player jumps or walks off it keeps going as fast as it was carried
+fn ride_platform(
+    mut player: Query<(
+        &mut Transform,
+        &mut LinearVelocity,
+        &mut GroundVelocity,
+        &GravityDirection
+    ), With<CharacterController>>,
+    grounds: Query<(&Position, &LinearVelocity, &AngularVelocity), Without<CharacterController>>,
+    collider_parents: Query<&ColliderParent>,
+    time: Res<Time>,
+) {
+    let Ok((mut transform, mut linear_velocity, mut ground_velocity, gravity_direction)) = player.get_single_mut() else {return;};
+
+    // the collider that was hit can be a child of the body that moves
+    let ground = ground_velocity.entity
+        .map(|ground| collider_parents.get(ground).map_or(ground, |parent| parent.get()))
+        .and_then(|ground| grounds.get(ground).ok());
+
+    let Some((position, ground_linear, ground_angular)) = ground else {
+        if ground_velocity.velocity != Vector::ZERO {
+            linear_velocity.0 += ground_velocity.velocity;
+            ground_velocity.inherited = gravity_direction.flatten(ground_velocity.velocity);
+            ground_velocity.velocity = Vector::ZERO;
+        }
+        return;
+    };
+
+    // how far the point under the player moves this step, turning around the body
+    let turn = Quat::from_scaled_axis(ground_angular.0 * time.delta_seconds());
+    let offset = transform.translation - position.0;
+    let carried = ground_linear.0 * time.delta_seconds() + turn * offset - offset;
+
+    transform.translation += carried;
+    transform.rotate_y(ground_angular.dot(gravity_direction.up()) * time.delta_seconds());
+
+    ground_velocity.velocity = carried / time.delta_seconds();
+}
+
+// the surface of the ground the player stands on, unmarked ground is the default one,
+// in the air it keeps the last one so the landing knows what it comes down on
+fn update_ground_surface(
+    mut player: Query<(&GroundVelocity, &mut GroundSurface), With<CharacterController>>,
+    surfaces: Query<&SurfaceMaterial>,
+) {
+    let Ok((ground_velocity, mut ground_surface)) = player.get_single_mut() else {return;};
+    let Some(ground) = ground_velocity.entity else {return;};
+
+    ground_surface.surface = surfaces.get(ground).map_or(Surface::Default, |material| material.0);
+}
+
+// looks for a wall right next to the player, in front of it and where it's moving
+fn detect_walls(
+    mut player: Query<(Entity, &Transform, &LinearVelocity, &GravityDirection, &mut WallNormal), With<CharacterController>>,
+    spatial_query: SpatialQuery,
+    tuning: Res<MovementTuning>,
+) {
+    let Ok((entity, transform, velocity, gravity_direction, mut wall_normal)) = player.get_single_mut() else {return;};
+
+    // the player is turned upright here, its forward is leaned back over to its gravity
+    let forward = gravity_direction.frame() * transform.forward();
+    let moving = Direction3d::new(gravity_direction.flatten(velocity.0)).ok();
+
+    wall_normal.0 = [Some(forward), moving]
+        .into_iter()
+        .flatten()
+        .filter_map(|direction| spatial_query.cast_ray(
+            transform.translation,
+            direction,
+            tuning.wall_check_distance,
+            true,
+            SpatialQueryFilter::from_excluded_entities([entity])
+        ))
+        // anything too flat is a floor or a ceiling
+        .map(|hit| hit.normal)
+        .find(|normal| normal.dot(gravity_direction.up()).abs() < 0.3);
+}
+
+// in the air against a wall the player slides down slowly and can jump off to the other side
+fn wall_slide(
+    mut player: Query<(
+        Entity,
+        &mut PlayerState,
+        &WallNormal,
+        &mut InputBuffer,
+        &mut PlayerDirection,
+        &mut Transform,
+        &LinearVelocity,
+        &mut GroundedHeight,
+        &GravityDirection
+    ), With<CharacterController>>,
+    mut movement_event: EventWriter<MovementAction>,
+    mut state_changed: EventWriter<StateChanged>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+) {
+    let Ok((player, mut state, wall_normal, mut input_buffer, mut direction, mut transform, velocity, mut grounded_height, gravity_direction)) = player.get_single_mut() else {return;};
+
+    let Some(normal) = wall_normal.0.filter(|_| !state.is_grounded()) else {
+        if *state == PlayerState::WallSlide {
+            change_state(player, &mut state, PlayerState::Fall, &mut state_changed);
+        }
+        return;
+    };
+
+    if *state != PlayerState::WallSlide {
+        // the player grabs the wall only on the way down
+        if velocity.dot(gravity_direction.up()) <= 0. {
+            change_state(player, &mut state, PlayerState::WallSlide, &mut state_changed);
+        }
+        return;
+    }
+
+    if input_buffer.jump_time.is_some()
+    && change_state(player, &mut state, PlayerState::Jump, &mut state_changed) {
+        input_buffer.jump_time = None;
+
+        // ratchet turns his back to the wall and jumps away from it
+        let away = gravity_direction.heading(normal).normalize_or_zero();
+        transform.rotation = Quat::from_rotation_y(f32::atan2(-away.x, -away.y));
+        direction.0 = away;
+
+        movement_event.send(MovementAction::WallJump(normal));
+
+        commands.spawn((
+            AudioBundle {
+                source: asset_server.load("jump.ogg"),
+                settings: PlaybackSettings {
+                    volume: Volume::new(0.07),
+                    speed: 0.75,
+                    mode: PlaybackMode::Despawn,
+                    ..default()
+                }
+            },
+        ));
+
+        return;
+    }
+
+    // facing the wall while sliding down
+    let away = gravity_direction.heading(normal);
+    transform.rotation = Quat::from_rotation_y(f32::atan2(away.x, away.y));
+    direction.0 = Vec2::ZERO;
+
+    // the camera treats the wall like the ground, so it follows the player up a wall jump shaft
+    grounded_height.0 = gravity_direction.height(transform.translation);
+
+    movement_event.send(MovementAction::WallSlide);
+}
+
+// grabs a ledge the player falls just short of, then shimmies along it, climbs up or lets go
+fn ledge_hang(
+    mut player: Query<(
+        Entity,
+        &mut PlayerState,
+        &WallNormal,
+        &mut LedgeGrab,
+        &mut InputBuffer,
+        &mut Transform,
+        &LinearVelocity,
+        &mut GroundedHeight,
+        &GravityDirection
+    ), With<CharacterController>>,
+    camera: Query<&Transform, (Without<CharacterController>, With<CameraIdentifier>)>,
+    intent: Query<&PlayerIntent, With<CharacterController>>,
+    spatial_query: SpatialQuery,
+    mut movement_event: EventWriter<MovementAction>,
+    mut state_changed: EventWriter<StateChanged>,
+    tuning: Res<MovementTuning>,
+    time: Res<Time>
+) {
+    let Ok((player, mut state, wall_normal, mut ledge, mut input_buffer, mut transform, velocity, mut grounded_height, gravity_direction)) = player.get_single_mut() else {return;};
+    let Ok(intent) = intent.get_single() else {return;};
+    let Ok(camera_transform) = camera.get_single() else {return;};
+
+    if *state != PlayerState::LedgeHang {
+        // ledges are grabbed only on the way down, and not right after letting go of one
+        if state.is_grounded() || velocity.dot(gravity_direction.up()) > 0. {return;}
+        if time.elapsed_seconds() < ledge.released_time + tuning.ledge_regrab_delay {return;}
+
+        let Some(normal) = wall_normal.0 else {return;};
+        let Some(point) = find_ledge(&spatial_query, player, transform.translation, normal, gravity_direction, &tuning) else {return;};
+
+        if change_state(player, &mut state, PlayerState::LedgeHang, &mut state_changed) {
+            ledge.point = point;
+            ledge.normal = gravity_direction.flatten(normal).normalize_or_zero();
+
+            // the capsule hangs right under the edge, facing the wall
+            let up = gravity_direction.up();
+            transform.translation += up * (gravity_direction.height(point) - tuning.ledge_hang_depth - gravity_direction.height(transform.translation));
+
+            let away = gravity_direction.heading(ledge.normal);
+            transform.rotation = Quat::from_rotation_y(f32::atan2(away.x, away.y));
+            grounded_height.0 = gravity_direction.height(point);
+
+            movement_event.send(MovementAction::LedgeHang(Vec3::ZERO));
+        }
+        return;
+    }
+
+    let stick = gravity_direction.from_heading(stick_direction(intent.movement, camera_transform, &transform, gravity_direction));
+
+    // jumping or pushing towards the wall climbs on top of the ledge
+    if input_buffer.jump_time.is_some() || stick.dot(-ledge.normal) > 0.7 {
+        input_buffer.jump_time = None;
+
+        if change_state(player, &mut state, PlayerState::Fall, &mut state_changed) {
+            transform.translation = ledge.point - ledge.normal * 0.1 + gravity_direction.up() * tuning.ledge_climb_height;
+            ledge.released_time = time.elapsed_seconds();
+
+            movement_event.send(MovementAction::LedgeHang(Vec3::ZERO));
+        }
+        return;
+    }
+
+    // crouching lets go
+    if intent.crouch {
+        if change_state(player, &mut state, PlayerState::Fall, &mut state_changed) {
+            ledge.released_time = time.elapsed_seconds();
+        }
+        return;
+    }
+
+    // the stick moves the player sideways along the ledge as long as there's still a ledge to hold on to
+    let along = gravity_direction.up().cross(ledge.normal);
+    let amount = stick.dot(along);
+    let mut shimmy = Vec3::ZERO;
+
+    if amount.abs() > 0.3 {
+        let side = along * amount.signum();
+
+        if let Some(point) = find_ledge(&spatial_query, player, transform.translation + side * 0.4, ledge.normal, gravity_direction, &tuning) {
+            ledge.point = point;
+            shimmy = side * tuning.shimmy_speed;
+        }
+    }
+
+    movement_event.send(MovementAction::LedgeHang(shimmy));
+}
+
+// the wind volumes push the player only while it's gliding
+fn wind(
+    player: Query<(&PlayerState, &Transform), With<CharacterController>>,
+    volumes: Query<&WindVolume>,
+    mut movement_event: EventWriter<MovementAction>,
+) {
+    let Ok((state, transform)) = player.get_single() else {return;};
+    if *state != PlayerState::Glide {return;}
+
+    let force: Vec3 = volumes.iter()
+        .filter(|volume| volume.bounds.contains(transform.translation))
+        .map(|volume| volume.force)
+        .sum();
+
+    if force != Vec3::ZERO {
+        movement_event.send(MovementAction::Wind(force));
+    }
+}
+
+// catches rails under the feet on the way down, moves the player along them
+// and jumps off them, to the sides too to reach a parallel rail
+fn grind(
+    mut player: Query<(
+        Entity,
+        &mut PlayerState,
+        &mut RailGrind,
+        &mut InputBuffer,
+        &mut Transform,
+        &LinearVelocity,
+        &mut GroundedHeight,
+        &CharacterShape,
+        &GravityDirection
+    ), With<CharacterController>>,
+    rails: Query<(Entity, &Rail)>,
+    camera: Query<&Transform, (Without<CharacterController>, With<CameraIdentifier>)>,
+    intent: Query<&PlayerIntent, With<CharacterController>>,
+    mut movement_event: EventWriter<MovementAction>,
+    mut state_changed: EventWriter<StateChanged>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    tuning: Res<MovementTuning>,
+    time: Res<Time>
+) {
+    let Ok((player, mut state, mut grind, mut input_buffer, mut transform, velocity, mut grounded_height, shape, gravity_direction)) = player.get_single_mut() else {return;};
+    let Ok(intent) = intent.get_single() else {return;};
+    let Ok(camera_transform) = camera.get_single() else {return;};
+
+    let up = gravity_direction.up();
+    let feet = transform.translation - up * shape.half_height;
+
+    if *state != PlayerState::Grind {
+        if velocity.dot(up) > 0. || !state.can_transition(PlayerState::Grind) {return;}
+
+        // the rail just left can't be caught again right away, the others can
+        let just_released = time.elapsed_seconds() < grind.released_time + tuning.rail_regrab_delay;
+
+        let closest = rails.iter()
+            .filter(|(rail_entity, _)| !(just_released && grind.rail == Some(*rail_entity)))
+            .map(|(rail_entity, rail)| (rail_entity, rail, rail.closest(feet)))
+            .filter(|(_, _, (_, point))| point.distance(feet) < tuning.grind_attach_distance)
+            .min_by(|(_, _, (_, a)), (_, _, (_, b))| a.distance(feet).total_cmp(&b.distance(feet)));
+
+        let Some((rail_entity, rail, (distance, _))) = closest else {return;};
+
+        if change_state(player, &mut state, PlayerState::Grind, &mut state_changed) {
+            let (_, tangent) = rail.sample(distance);
+            let along = velocity.dot(tangent);
+
+            // the player keeps going the way it was moving, or the way it's facing when it lands still
+            let facing = gravity_direction.frame() * *transform.forward();
+            grind.direction = if along.abs() > 0.5 {along.signum()} else {facing.dot(tangent).signum()};
+            grind.speed = along.abs().max(tuning.grind_speed);
+            grind.distance = distance;
+            grind.rail = Some(rail_entity);
+        }
+        return;
+    }
+
+    let Some((_, rail)) = grind.rail.and_then(|rail| rails.get(rail).ok()) else {
+        change_state(player, &mut state, PlayerState::Fall, &mut state_changed);
+        return;
+    };
+
+    let (_, tangent) = rail.sample(grind.distance);
+    let forward = tangent * grind.direction;
+
+    if input_buffer.jump_time.is_some() && change_state(player, &mut state, PlayerState::Jump, &mut state_changed) {
+        input_buffer.jump_time = None;
+        grind.released_time = time.elapsed_seconds();
+
+        // pushing the stick sideways hops to the side, otherwise it's a jump straight up
+        let right = forward.cross(up).normalize_or_zero();
+        let stick = gravity_direction.from_heading(stick_direction(intent.movement, camera_transform, &transform, gravity_direction));
+        let side = stick.dot(right);
+
+        if side.abs() > 0.5 {
+            movement_event.send(MovementAction::RailHop(right * side.signum()));
+        }
+        else {
+            movement_event.send(MovementAction::Jump);
+        }
+
+        commands.spawn((
+            AudioBundle {
+                source: asset_server.load("jump.ogg"),
+                settings: PlaybackSettings {
+                    volume: Volume::new(0.07),
+                    speed: 0.75,
+                    mode: PlaybackMode::Despawn,
+                    ..default()
+                }
+            },
+        ));
+        return;
+    }
+
+    grind.distance += grind.direction * grind.speed * time.delta_seconds();
+
+    // at the end of the rail the player flies off with the speed it had
+    if grind.distance < 0. || grind.distance > rail.length() {
+        grind.released_time = time.elapsed_seconds();
+        change_state(player, &mut state, PlayerState::Fall, &mut state_changed);
+        return;
+    }
+
+    let (point, tangent) = rail.sample(grind.distance);
+    let forward = tangent * grind.direction;
+
+    let heading = gravity_direction.heading(forward);
+    transform.rotation = Quat::from_rotation_y(f32::atan2(-heading.x, -heading.y));
+    grounded_height.0 = gravity_direction.height(transform.translation);
+
+    // the velocity that puts the feet on the rail at the end of this step
+    let target = point + up * shape.half_height;
+    movement_event.send(MovementAction::Grind((target - transform.translation) / time.delta_seconds()));
+}
+
+// falling deep enough into a water volume starts swimming, the player floats at the surface
+// and dives with crouch for as long as it has oxygen
+fn swim(
+    mut player: Query<(
+        Entity,
+        &mut PlayerState,
+        &mut InputBuffer,
+        &mut Transform,
+        &mut PlayerDirection,
+        &LinearVelocity,
+        &mut GroundedHeight,
+        &mut Oxygen,
+        &GravityDirection
+    ), With<CharacterController>>,
+    volumes: Query<&WaterVolume>,
+    camera: Query<&Transform, (Without<CharacterController>, With<CameraIdentifier>)>,
+    intent: Query<&PlayerIntent, With<CharacterController>>,
+    mut movement_event: EventWriter<MovementAction>,
+    mut state_changed: EventWriter<StateChanged>,
+    tuning: Res<MovementTuning>,
+    time: Res<Time>
+) {
+    let Ok((player, mut state, mut input_buffer, mut transform, mut direction, velocity, mut grounded_height, mut oxygen, gravity_direction)) = player.get_single_mut() else {return;};
+    let Ok(intent) = intent.get_single() else {return;};
+    let Ok(camera_transform) = camera.get_single() else {return;};
+
+    // the player catches its breath whenever it isn't under the water
+    if *state != PlayerState::Dive {
+        oxygen.0 = (oxygen.0 + tuning.oxygen_time / tuning.oxygen_refill_time * time.delta_seconds()).min(tuning.oxygen_time);
+    }
+
+    let surface = volumes.iter()
+        .filter_map(|volume| volume.surface_at(transform.translation))
+        .reduce(f32::max);
+
+    // the water stays level, how deep under the float height the player is is measured along its up
+    let up = gravity_direction.up();
+    let depth = surface.map(|surface| gravity_direction.height(Vec3::Y * (surface - tuning.swim_float_depth - transform.translation.y)));
+
+    if !state.is_swimming() {
+        let Some(depth) = depth else {return;};
+
+        if depth > 0. {
+            change_state(player, &mut state, PlayerState::Swim, &mut state_changed);
+        }
+        return;
+    }
+
+    // out of the water, or carried up well above the surface
+    let Some(depth) = depth.filter(|depth| *depth > -0.25) else {
+        change_state(player, &mut state, PlayerState::Fall, &mut state_changed);
+        return;
+    };
+
+    grounded_height.0 = gravity_direction.height(transform.translation);
+
+    let heading = stick_direction(intent.movement, camera_transform, &transform, gravity_direction);
+    let stick = gravity_direction.from_heading(heading);
+
+    // the player turns to swim the way the stick points
+    if heading != Vec2::ZERO {
+        let target_rotation = Quat::from_rotation_y(f32::atan2(-heading.x, -heading.y));
+        transform.rotation = transform.rotation.slerp(target_rotation, 1. - decay(tuning.swim_turn_rate, &time));
+
+        direction.0 = Vec2::new(transform.forward().x, transform.forward().z).normalize_or_zero();
+    }
+    else {
+        direction.0 = Vec2::ZERO;
+    }
+
+    if *state == PlayerState::Swim {
+        if input_buffer.jump_time.is_some() && change_state(player, &mut state, PlayerState::Jump, &mut state_changed) {
+            input_buffer.jump_time = None;
+            movement_event.send(MovementAction::SwimJump);
+            return;
+        }
+
+        // a quarter of the air is needed to go under
+        if intent.crouch && oxygen.0 > tuning.oxygen_time * 0.25 && change_state(player, &mut state, PlayerState::Dive, &mut state_changed) {
+            movement_event.send(MovementAction::Swim(-up * tuning.dive_impulse / time.delta_seconds()));
+            return;
+        }
+
+        // a critically damped spring keeps the player bobbing at the surface without overshooting it
+        let spring = tuning.swim_buoyancy * depth
+            - 2. * tuning.swim_buoyancy.sqrt() * velocity.dot(up);
+
+        movement_event.send(MovementAction::Swim(stick * tuning.swim_acceleration + up * spring));
+        return;
+    }
+
+    oxygen.0 -= time.delta_seconds();
+
+    // out of air or back up at the surface the player swims again
+    if oxygen.0 <= 0. || (depth < 0. && velocity.dot(up) > 0.) {
+        oxygen.0 = oxygen.0.max(0.);
+        change_state(player, &mut state, PlayerState::Swim, &mut state_changed);
+        return;
+    }
+
+    // jump swims up and crouch swims down, letting go floats up slowly
+    let vertical = if intent.jump_held {
+        tuning.dive_acceleration
+    }
+    else if intent.crouch {
+        -tuning.dive_acceleration
+    }
+    else {
+        tuning.dive_buoyancy
+    };
+
+    movement_event.send(MovementAction::Swim(stick * tuning.dive_acceleration + up * vertical));
+}
+
+// walking into a ladder or a climbable wall, or falling against one, grabs it,
+// the stick climbs around on it, and the player climbs over the top or backflips off it
+fn climb(
+    mut player: Query<(
+        Entity,
+        &mut PlayerState,
+        &mut Climbing,
+        &mut InputBuffer,
+        &mut PlayerDirection,
+        &mut Transform,
+        &ShapeHits,
+        &Rotation,
+        &mut GroundedHeight,
+        &CharacterShape,
+        &GravityDirection
+    ), With<CharacterController>>,
+    climbables: Query<(), With<Climbable>>,
+    ladders: Query<&Ladder>,
+    camera: Query<&Transform, (Without<CharacterController>, With<CameraIdentifier>)>,
+    intent: Query<&PlayerIntent, With<CharacterController>>,
+    spatial_query: SpatialQuery,
+    mut movement_event: EventWriter<MovementAction>,
+    mut state_changed: EventWriter<StateChanged>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    tuning: Res<MovementTuning>,
+    time: Res<Time>
+) {
+    let Ok((player, mut state, mut climbing, mut input_buffer, mut direction, mut transform, hits, rotation, mut grounded_height, shape, gravity_direction)) = player.get_single_mut() else {return;};
+    let Ok(intent) = intent.get_single() else {return;};
+    let Ok(camera_transform) = camera.get_single() else {return;};
+
+    if *state != PlayerState::Climb {
+        if !state.can_transition(PlayerState::Climb) || time.elapsed_seconds() < climbing.released_time + tuning.climb_regrab_delay {return;}
+
+        let forward = gravity_direction.frame() * *transform.forward();
+        let Some(normal) = find_climbable(&spatial_query, player, transform.translation, forward, gravity_direction, &climbables, &ladders, &tuning) else {return;};
+
+        // on the ground the player has to walk into it, in the air touching it is enough
+        let stick = gravity_direction.from_heading(stick_direction(intent.movement, camera_transform, &transform, gravity_direction));
+        if state.is_grounded() && stick.dot(-normal) < 0.7 {return;}
+
+        if change_state(player, &mut state, PlayerState::Climb, &mut state_changed) {
+            climbing.normal = normal;
+            movement_event.send(MovementAction::Climb(normal, Vec2::ZERO));
+        }
+        return;
+    }
+
+    // the wall can curve under the player, but if it's gone there's nothing to hold on to
+    let Some(normal) = find_climbable(&spatial_query, player, transform.translation, -climbing.normal, gravity_direction, &climbables, &ladders, &tuning) else {
+        climbing.released_time = time.elapsed_seconds();
+        change_state(player, &mut state, PlayerState::Fall, &mut state_changed);
+        return;
+    };
+    climbing.normal = normal;
+
+    if input_buffer.jump_time.is_some() && change_state(player, &mut state, PlayerState::Jump, &mut state_changed) {
+        input_buffer.jump_time = None;
+        climbing.released_time = time.elapsed_seconds();
+
+        // ratchet turns his back to the wall and flips away from it
+        let away = gravity_direction.heading(normal).normalize_or_zero();
+        transform.rotation = Quat::from_rotation_y(f32::atan2(-away.x, -away.y));
+        direction.0 = away;
+
+        movement_event.send(MovementAction::ClimbJump(normal));
+
+        commands.spawn((
+            AudioBundle {
+                source: asset_server.load("jump.ogg"),
+                settings: PlaybackSettings {
+                    volume: Volume::new(0.07),
+                    speed: 0.75,
+                    mode: PlaybackMode::Despawn,
+                    ..default()
+                }
+            },
+        ));
+        return;
+    }
+
+    // crouching lets go
+    if intent.crouch {
+        if change_state(player, &mut state, PlayerState::Fall, &mut state_changed) {
+            climbing.released_time = time.elapsed_seconds();
+        }
+        return;
+    }
+
+    // facing the wall while climbing
+    let away = gravity_direction.heading(normal);
+    transform.rotation = Quat::from_rotation_y(f32::atan2(away.x, away.y));
+    grounded_height.0 = gravity_direction.height(transform.translation);
+
+    // the stick goes up and down the wall and sideways along it, as long as there's something to hold on to there
+    let up = gravity_direction.up();
+    let mut climb_direction = intent.movement;
+    let right = up.cross(normal);
+
+    if climb_direction.x != 0.
+    && find_climbable(&spatial_query, player, transform.translation + right * climb_direction.x.signum() * shape.radius, -normal, gravity_direction, &climbables, &ladders, &tuning).is_none() {
+        climb_direction.x = 0.;
+    }
+
+    if climb_direction.y > 0.
+    && find_climbable(&spatial_query, player, transform.translation + up * shape.half_height, -normal, gravity_direction, &climbables, &ladders, &tuning).is_none() {
+        // at the top the player climbs over onto whatever is behind the edge
+        if let Some(top) = find_climb_top(&spatial_query, player, transform.translation, normal, up, shape) {
+            if change_state(player, &mut state, PlayerState::Idle, &mut state_changed) {
+                climbing.released_time = time.elapsed_seconds();
+                transform.translation = top + up * (shape.half_height + 0.05);
+                direction.0 = Vec2::ZERO;
+
+                movement_event.send(MovementAction::Climb(normal, Vec2::ZERO));
+            }
+            return;
+        }
+
+        climb_direction.y = 0.;
+    }
+
+    // climbing down onto the ground puts the player back on its feet
+    let on_ground = hits.iter().any(|hit| rotation.rotate(-hit.normal2).dot(up) > 0.7);
+
+    if climb_direction.y < 0. && on_ground {
+        if change_state(player, &mut state, PlayerState::Idle, &mut state_changed) {
+            climbing.released_time = time.elapsed_seconds();
+            direction.0 = Vec2::ZERO;
+        }
+        return;
+    }
+
+    // the direction stays a heading on the ground, on the wall that's only the sideways part of the climb
+    direction.0 = gravity_direction.heading(right * climb_direction.x);
+
+    movement_event.send(MovementAction::Climb(normal, climb_direction));
+}
+
+// the normal of the ladder the player is in and facing, or of the climbable wall right in front of it
+fn find_climbable(
+    spatial_query: &SpatialQuery,
+    player: Entity,
+    position: Vec3,
+    forward: Vec3,
+    gravity_direction: &GravityDirection,
+    climbables: &Query<(), With<Climbable>>,
+    ladders: &Query<&Ladder>,
+    tuning: &MovementTuning
+) -> Option<Vec3> {
+    let forward = gravity_direction.flatten(forward).normalize_or_zero();
+
+    if let Some(ladder) = ladders.iter().find(|ladder| ladder.bounds.contains(position) && forward.dot(-ladder.normal(gravity_direction.up())) > 0.5) {
+        return Some(ladder.normal(gravity_direction.up()));
+    }
+
+    let hit = spatial_query.cast_ray(
+        position,
+        Direction3d::new(forward).ok()?,
+        tuning.wall_check_distance,
+        true,
+        SpatialQueryFilter::from_excluded_entities([player])
+    )?;
+
+    // only steep enough walls, the floors of a climbable mesh are walked on
+    if !climbables.contains(hit.entity) || hit.normal.dot(gravity_direction.up()).abs() >= 0.3 {return None;}
+
+    Some(gravity_direction.flatten(hit.normal).normalize_or_zero())
+}
+
+// the walkable ground just behind the top of a climb, if the player fits there
+fn find_climb_top(
+    spatial_query: &SpatialQuery,
+    player: Entity,
+    position: Vec3,
+    wall_normal: Vec3,
+    up: Vec3,
+    shape: &CharacterShape
+) -> Option<Vec3> {
+    let above = position + up * (shape.half_height + 0.5) - wall_normal * (shape.radius + 0.2);
+
+    let hit = spatial_query.cast_ray(
+        above,
+        Direction3d::new(-up).ok()?,
+        shape.half_height * 2. + 0.5,
+        true,
+        SpatialQueryFilter::from_excluded_entities([player])
+    )?;
+
+    if hit.normal.dot(up) < 0.7 {return None;}
+
+    Some(above - up * hit.time_of_impact)
+}
+
+fn find_ledge(
+    spatial_query: &SpatialQuery,
+    player: Entity,
+    position: Vec3,
+    wall_normal: Vec3,
+    gravity_direction: &GravityDirection,
+    tuning: &MovementTuning
+) -> Option<Vec3> {
+    let up = gravity_direction.up();
+    let into_wall = Direction3d::new(-gravity_direction.flatten(wall_normal)).ok()?;
+    let reach = position + up * tuning.ledge_reach;
+
+    // if there's still wall at the top of the reach it isn't a ledge
+    if spatial_query.cast_ray(
+        reach,
+        into_wall,
+        tuning.wall_check_distance + 0.15,
+        true,
+        SpatialQueryFilter::from_excluded_entities([player])
+    ).is_some() {
+        return None;
+    }
+
+    let above = reach + *into_wall * (tuning.wall_check_distance + 0.15);
+
+    let hit = spatial_query.cast_ray(
+        above,
+        Direction3d::new(-up).ok()?,
+        tuning.ledge_reach,
+        true,
+        SpatialQueryFilter::from_excluded_entities([player])
+    )?;
+
+    // too steep to stand on, or so low the player will just land on it
+    if hit.normal.dot(up) < 0.7 {return None;}
+
+    let point = above - up * hit.time_of_impact;
+
+    if gravity_direction.height(point - position) < tuning.ledge_min_height {return None;}
+
+    Some(point)
+}
+
+// remembers when jump and swing were pressed and forgets them once the buffer window is over
+fn buffer_input(
+    mut player: Query<(&PlayerIntent, &mut InputBuffer), With<CharacterController>>,
+    time: Res<Time>,
+) {
+    let Ok((intent, mut input_buffer)) = player.get_single_mut() else {return;};
+
+    let now = time.elapsed_seconds();
+    let window = input_buffer.window;
+
+    if intent.jump_pressed {
+        input_buffer.jump_time = Some(now);
+    }
+    if intent.swing_pressed {
+        input_buffer.swing_time = Some(now);
+    }
+    if intent.grapple_pressed {
+        input_buffer.grapple_time = Some(now);
+    }
+
+    if input_buffer.jump_time.is_some_and(|pressed| now - pressed > window) {
+        input_buffer.jump_time = None;
+    }
+    if input_buffer.swing_time.is_some_and(|pressed| now - pressed > window) {
+        input_buffer.swing_time = None;
+    }
+    if input_buffer.grapple_time.is_some_and(|pressed| now - pressed > window) {
+        input_buffer.grapple_time = None;
+    }
+}
+
+// on the ground, or just off a ledge so the crouch moves can still start,
+// but never after a jump or once the coyote time is over
+fn can_crouch(state: PlayerState, coyote_time: &CoyoteTime, jump_counter: &JumpCounter, time: &Time) -> bool {
+    state.is_grounded() || in_coyote_time(coyote_time, jump_counter, time)
+}
+
+// true for a short while after walking off a ledge without jumping
+pub fn in_coyote_time(coyote_time: &CoyoteTime, jump_counter: &JumpCounter, time: &Time) -> bool {
+    jump_counter.counter < 1.
+    && time.elapsed_seconds() < coyote_time.grounded_time + coyote_time.window
+}
+
+// the direction on the ground the stick points to from the camera point of view
+fn stick_direction(movement: Vec2, camera_transform: &Transform, player_transform: &Transform, gravity_direction: &GravityDirection) -> Vec2 {
+    let Some(controller_axes) = movement.try_normalize() else {return Vec2::ZERO;};
+
+    let angle = get_angle(controller_axes.x, controller_axes.y) - get_camera_angle(camera_transform, player_transform, gravity_direction);
+    let forward = Quat::from_rotation_y(angle) * Vec3::NEG_Z;
+
+    Vec2::new(forward.x, forward.z)
+}
+
+fn get_angle(cos: f32, sin: f32) -> f32 {
+    let mut angle = cos.acos();
+
+    if sin < 0. {
         angle *= -1. ;
-    } 
+    }
 
     if angle < 0. {
         angle += 6.28319;
     }
 
     return angle;
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn time_at(seconds: f32) -> Time {
+        let mut time = Time::<()>::default();
+        time.advance_to(Duration::from_secs_f32(seconds));
+
+        time
+    }
+
+    fn left_ground_at(seconds: f32) -> CoyoteTime {
+        CoyoteTime {
+            window: 0.1,
+            grounded_time: seconds
+        }
+    }
+
+    fn jumps(counter: f32) -> JumpCounter {
+        JumpCounter {
+            counter,
+            jump_time: 0.
+        }
+    }
+
+    #[test]
+    fn crouching_on_the_ground_is_allowed() {
+        assert!(can_crouch(PlayerState::Walk, &left_ground_at(0.), &jumps(0.), &time_at(5.)));
+    }
+
+    #[test]
+    fn crouching_in_coyote_time_is_allowed() {
+        assert!(can_crouch(PlayerState::Fall, &left_ground_at(1.), &jumps(0.), &time_at(1.05)));
+    }
+
+    #[test]
+    fn crouching_in_the_air_after_the_coyote_time_is_refused() {
+        assert!(!can_crouch(PlayerState::Fall, &left_ground_at(1.), &jumps(0.), &time_at(1.5)));
+    }
+
+    #[test]
+    fn crouching_in_the_air_after_a_jump_is_refused() {
+        assert!(!can_crouch(PlayerState::Jump, &left_ground_at(1.), &jumps(1.), &time_at(1.05)));
+        assert!(!can_crouch(PlayerState::Fall, &left_ground_at(1.), &jumps(1.), &time_at(1.05)));
+    }
+}
@@ -1,22 +1,29 @@
 use bevy::prelude::*;
-use bevy_xpbd_3d::components::{GravityScale, LinearVelocity};
+use bevy_xpbd_3d::prelude::{Gravity, GravityScale, LinearVelocity, SpatialQuery, SpatialQueryFilter};
 
-use crate::{player::*, player_input::*};
+use crate::{movement_tuning::MovementTuning, packs::{Pack, PackUpgrades}, player::*, player_controls::playing, player_state::{run_state_hooks, PlayerState}, surfaces::Surface};
 
 pub struct PlayerMovementPlugin;
 
 impl Plugin for PlayerMovementPlugin {
     fn build(&self, app: &mut App) {
         app 
-            .add_systems(Update, (
+            .add_systems(FixedUpdate, (
                 movement,
-                damp_movement
-            ));
+                damp_movement,
+                step_up
+            ).chain().after(run_state_hooks).run_if(playing));
     }
 }
 
-fn movement(
+// how much of a value is left after decaying at the given rate for this step
+pub fn decay(rate: f32, time: &Time) -> f32 {
+    (-rate * time.delta_seconds()).exp()
+}
+
+pub fn movement(
     time: Res<Time>,
+    tuning: Res<MovementTuning>,
     mut movement_event: EventReader<MovementAction>,
     mut controllers: Query<(
         &MovementAcceleration,
@@ -24,23 +31,48 @@ fn movement(
         &DoubleJumpImpulse,
         &mut LinearVelocity,
         &mut JumpCounter,
-        &mut GravityScale
+        &PlayerState,
+        &GroundNormal,
+        &PackUpgrades,
+        &GravityDirection,
+        &GroundSurface
     ), With<CharacterController>>
 ) {
     let Ok((
         movement_acceleration,
         jump_impulse,
         double_jump_impulse,
-        mut linear_velocity,
+        mut world_velocity,
         mut jump_counter,
-        mut gravity_scale
+        state,
+        ground_normal,
+        packs,
+        gravity_direction,
+        ground_surface
     )) = controllers.get_single_mut() else {return;};
 
+    // the actions are worked out as if the player were upright, then turned back to its gravity
+    let frame = gravity_direction.frame();
+    let mut linear_velocity = LinearVelocity(frame.inverse() * world_velocity.0);
+    let ground_normal = GroundNormal(frame.inverse() * ground_normal.0);
+
     for action in movement_event.read() {
-        match action {
+        match &action.rotated(frame.inverse()) {
             MovementAction::Walk(direction) => {
-                linear_velocity.x += direction.x * movement_acceleration.0 * time.delta_seconds();
-                linear_velocity.z += direction.y * movement_acceleration.0 * time.delta_seconds();
+                let acceleration = Vec3::new(direction.x, 0., direction.y) * movement_acceleration.0;
+
+                // on the ground the player walks along the surface, as fast uphill and downhill as on flat ground,
+                // and gets going slower on slippery or sticky ground
+                let acceleration = if state.is_grounded() {
+                    let (_, acceleration_scale) = surface_grip(ground_surface.surface, &tuning);
+
+                    along_surface(acceleration, ground_normal.0).normalize_or_zero() * acceleration.length() * acceleration_scale
+                }
+                else {
+                    acceleration
+                };
+
+                linear_velocity.0 += acceleration * time.delta_seconds();
 
             }
             MovementAction::Jump => {
@@ -52,45 +84,149 @@ fn movement(
                 jump_counter.jump_time = time.elapsed_seconds();
                 linear_velocity.y = double_jump_impulse.0;
                 jump_counter.counter += 1.;
+
+                // the heli-pack spins the player higher
+                if packs.equipped == Some(Pack::Heli) {
+                    linear_velocity.y += tuning.heli_spin_boost;
+                }
             }
-            MovementAction::Gliding => {
-                linear_velocity.y = -1.35;
-                linear_velocity.x *= 0.90;
-                linear_velocity.z *= 0.90;
+            MovementAction::Gliding(facing, throttle) => {
+                let facing = Vec3::new(facing.x, 0., facing.y);
+
+                // the packs carry the player forward, without one it keeps its speed
+                // going the way it faces and slows down
+                let (fall_speed, glide_velocity) = match packs.equipped {
+                    None => (tuning.glide_fall_speed, Vec3::ZERO),
+                    // with the stick released the heli-pack hovers in place
+                    Some(Pack::Heli) => (tuning.heli_fall_speed, facing * tuning.heli_glide_speed * *throttle),
+                    Some(Pack::Thruster) => (tuning.thruster_fall_speed, facing * tuning.thruster_glide_speed)
+                };
+
+                if packs.equipped.is_none() {
+                    let speed = Vec2::new(linear_velocity.x, linear_velocity.z).length();
+
+                    linear_velocity.x = facing.x * speed;
+                    linear_velocity.z = facing.z * speed;
+                }
+
+                // only the fall is limited, so an updraft can lift the player
+                linear_velocity.y = linear_velocity.y.max(-fall_speed);
+                linear_velocity.x = glide_velocity.x + (linear_velocity.x - glide_velocity.x) * decay(tuning.glide_damping, &time);
+                linear_velocity.z = glide_velocity.z + (linear_velocity.z - glide_velocity.z) * decay(tuning.glide_damping, &time);
+            }
+            MovementAction::Wind(force) => {
+                linear_velocity.0 += *force * time.delta_seconds();
+            }
+            MovementAction::Swim(acceleration) => {
+                linear_velocity.0 += *acceleration * time.delta_seconds();
+            }
+            MovementAction::SwimJump => {
+                jump_counter.jump_time = time.elapsed_seconds();
+                jump_counter.counter = 1.;
+
+                linear_velocity.y = tuning.swim_jump_impulse;
             }
             MovementAction::Sideflip(direction) => {
-                linear_velocity.x += linear_velocity.x / 1.8 + direction.x * 4.;
-                linear_velocity.z += linear_velocity.z / 1.8 + direction.y * 4.;
-                linear_velocity.y = 12.2;
+                linear_velocity.x += linear_velocity.x / 1.8 + direction.x * tuning.sideflip_push;
+                linear_velocity.z += linear_velocity.z / 1.8 + direction.y * tuning.sideflip_push;
+                linear_velocity.y = tuning.sideflip_impulse;
 
             }
             MovementAction::Longjump(direction) => {
-                
-                gravity_scale.0 = 1.;
+                linear_velocity.y = tuning.longjump_impulse;
+                let normalized_direction = direction.normalize_or_zero();
 
-                linear_velocity.y = 5.5;
-                linear_velocity.x = direction.normalize().x * 14.7;
-                linear_velocity.z = direction.normalize().y * 14.7;
+                linear_velocity.x = normalized_direction.x * tuning.longjump_speed;
+                linear_velocity.z = normalized_direction.y * tuning.longjump_speed;
 
             }
             MovementAction::Highjump1 => {
-                gravity_scale.0 = 1.3;
-                linear_velocity.y = 6.5;
+                linear_velocity.y = tuning.highjump_impulse;
+
+                if packs.equipped == Some(Pack::Thruster) {
+                    linear_velocity.y += tuning.thruster_highjump_boost;
+                }
 
             }
             MovementAction::Highjump2 => {
-                linear_velocity.y = 3.8;
+                linear_velocity.y = tuning.highjump_second_impulse;
+            }
+            MovementAction::Slide(normal, steering) => {
+                // gravity along the slope pulls the player downhill, the stick only pushes sideways
+                let downhill = (Vec3::NEG_Y - *normal * normal.dot(Vec3::NEG_Y)).normalize_or_zero();
+                let steering = Vec3::new(steering.x, 0., steering.y);
+                let steering = steering - downhill * steering.dot(downhill);
+                let steering = steering - *normal * steering.dot(*normal);
+
+                linear_velocity.0 += downhill * tuning.slide_acceleration * time.delta_seconds();
+                linear_velocity.0 += steering * tuning.slide_steering * time.delta_seconds();
+            }
+            MovementAction::SlideJump(normal) => {
+                jump_counter.jump_time = time.elapsed_seconds();
+                jump_counter.counter = 1.;
+
+                let push = Vec3::new(normal.x, 0., normal.z).normalize_or_zero();
+
+                linear_velocity.x = push.x * tuning.slide_jump_push;
+                linear_velocity.z = push.z * tuning.slide_jump_push;
+                linear_velocity.y = tuning.slide_jump_impulse;
+            }
+            MovementAction::WallSlide => {
+                linear_velocity.y = linear_velocity.y.max(-tuning.wall_slide_speed);
+            }
+            MovementAction::WallJump(normal) => {
+                jump_counter.jump_time = time.elapsed_seconds();
+                jump_counter.counter = 2.;
+
+                let push = Vec3::new(normal.x, 0., normal.z).normalize_or_zero();
+
+                linear_velocity.x = push.x * tuning.wall_jump_push;
+                linear_velocity.z = push.z * tuning.wall_jump_push;
+                linear_velocity.y = tuning.wall_jump_impulse;
+            }
+            MovementAction::LedgeHang(shimmy) => {
+                linear_velocity.0 = *shimmy;
+            }
+            MovementAction::Grind(velocity) => {
+                linear_velocity.0 = *velocity;
+            }
+            MovementAction::Grapple(velocity) => {
+                linear_velocity.0 = *velocity;
+            }
+            MovementAction::Climb(normal, direction) => {
+                let right = Vec3::Y.cross(*normal);
+
+                linear_velocity.0 = right * direction.x * tuning.climb_lateral_speed + Vec3::Y * direction.y * tuning.climb_speed;
+            }
+            MovementAction::ClimbJump(normal) => {
+                jump_counter.jump_time = time.elapsed_seconds();
+                jump_counter.counter = 1.;
+
+                let push = Vec3::new(normal.x, 0., normal.z).normalize_or_zero();
+
+                linear_velocity.x = push.x * tuning.climb_jump_push;
+                linear_velocity.z = push.z * tuning.climb_jump_push;
+                linear_velocity.y = tuning.climb_jump_impulse;
+            }
+            MovementAction::RailHop(side) => {
+                jump_counter.jump_time = time.elapsed_seconds();
+                jump_counter.counter += 1.;
+
+                linear_velocity.0 += *side * tuning.rail_hop_push;
+                linear_velocity.y = tuning.rail_hop_impulse;
             }
             MovementAction::Swing(direction) => {
-                let normalized_direction = direction.normalize();
+                let normalized_direction = direction.normalize_or_zero();
 
-                linear_velocity.x += normalized_direction.x * 5.;
-                linear_velocity.z += normalized_direction.y * 5.;
+                linear_velocity.x += normalized_direction.x * tuning.swing_push;
+                linear_velocity.z += normalized_direction.y * tuning.swing_push;
 
             }
         }
     }
 
+    world_velocity.0 = frame * linear_velocity.0;
+
 
 
 }
@@ -98,32 +234,135 @@ fn movement(
 
 
 
+// how much the ground changes the damping and the acceleration of the walk
+fn surface_grip(surface: Surface, tuning: &MovementTuning) -> (f32, f32) {
+    match surface {
+        Surface::Ice => (tuning.ice_damping_scale, tuning.ice_acceleration_scale),
+        Surface::Mud => (tuning.mud_damping_scale, tuning.mud_acceleration_scale),
+        Surface::Default | Surface::Metal | Surface::Grass => (1., 1.)
+    }
+}
+
+// the part of a vector that runs along a surface with this normal
+fn along_surface(vector: Vec3, normal: Vec3) -> Vec3 {
+    vector - normal * vector.dot(normal)
+}
+
 fn damp_movement(
     mut player: Query<(
         &MovementDampingFactor, 
         &mut LinearVelocity, 
-        Has<Grounded>,
-        Has<SideflipL>,
-        Has<SideflipR>,
-        Has<Longjump>,
-        Has<Highjump>
+        &PlayerState,
+        &GroundVelocity,
+        &GroundNormal,
+        &GravityScale,
+        &PackUpgrades,
+        &GravityDirection,
+        &GroundSurface
     ), With<CharacterController>>,
+    tuning: Res<MovementTuning>,
+    gravity: Res<Gravity>,
+    time: Res<Time>,
 
 ) {
-    let Ok((damping_factor, mut linear_velocity, grounded, sideflip_l, sideflip_r, longjump, highjump)) = player.get_single_mut() else {return;};
+    let Ok((damping_factor, mut linear_velocity, state, ground_velocity, ground_normal, gravity_scale, packs, gravity_direction, ground_surface)) = player.get_single_mut() else {return;};
+
+    if state.is_grounded() {
+        // gravity doesn't drag the player down walkable slopes
+        let player_gravity = gravity_direction.down * gravity.0.length() * gravity_scale.0;
+        linear_velocity.0 -= along_surface(player_gravity, ground_normal.0) * time.delta_seconds();
+
+        // the speed is limited along the slope, not just sideways, ice lets the player slide on and mud holds it back
+        let (damping_scale, _) = surface_grip(ground_surface.surface, &tuning);
+        let along_ground = along_surface(linear_velocity.0, ground_normal.0);
+        linear_velocity.0 -= along_ground * (1. - decay(damping_factor.0 * damping_scale, &time));
+
+        return;
+    }
+
+    // only the speed across the way gravity pulls is damped in the air
+    let up = gravity_direction.up();
+    let vertical = up * linear_velocity.dot(up);
+    let horizontal = linear_velocity.0 - vertical;
+
+    // the water slows the player down every way, at the surface the buoyancy settles the height on its own
+    if state.is_swimming() {
+        let vertical_decay = if *state == PlayerState::Dive {decay(tuning.water_damping, &time)} else {1.};
+
+        linear_velocity.0 = horizontal * decay(tuning.water_damping, &time) + vertical * vertical_decay;
+        return;
+    }
+
+    // gliding with a pack the glide sets the speed on its own
+    if *state == PlayerState::Glide && packs.equipped.is_some() {return;}
 
-    if grounded {
-        linear_velocity.x *= damping_factor.0;
-        linear_velocity.z *= damping_factor.0;
+    let damping = if *state == PlayerState::Highjump {
+        tuning.highjump_damping
     }
-    else if highjump {
-        linear_velocity.x *= 0.85;
-        linear_velocity.z *= 0.85;
+    else if *state == PlayerState::Slide {
+        tuning.slide_damping
     }
-    else if !sideflip_l && !sideflip_r && !longjump {
-        linear_velocity.x *= 0.927;
-        linear_velocity.z *= 0.927;
+    else if !matches!(state, PlayerState::SideflipL | PlayerState::SideflipR | PlayerState::Longjump | PlayerState::Grind | PlayerState::Swingshot | PlayerState::Climb) {
+        tuning.air_damping
     }
+    else {
+        return;
+    };
+
+    // after jumping off a platform the player slows down to the platform speed, not to a stop
+    let inherited = ground_velocity.inherited;
+
+    linear_velocity.0 = vertical + inherited + (horizontal - inherited) * decay(damping, &time);
     
 
-}
\ No newline at end of file
+}
+
+// walking into something low enough puts the player on top of it instead of stopping,
+// it's checked where the player is going to move this step
+pub fn step_up(
+    mut player: Query<(
+        Entity,
+        &mut Transform,
+        &LinearVelocity,
+        &PlayerState,
+        &MaxStepHeight,
+        &MaxSlopeAngle,
+        &CharacterShape,
+        &GravityDirection
+    ), With<CharacterController>>,
+    spatial_query: SpatialQuery,
+) {
+    let Ok((entity, mut transform, linear_velocity, state, max_step_height, max_slope_angle, shape, gravity_direction)) = player.get_single_mut() else {return;};
+
+    if !state.is_grounded() {return;}
+
+    let up = gravity_direction.up();
+    let Ok(direction) = Direction3d::new(linear_velocity.0 - up * linear_velocity.dot(up)) else {return;};
+    let Ok(down) = Direction3d::new(gravity_direction.down) else {return;};
+
+    let reach = shape.radius + 0.1;
+    let feet = transform.translation - up * (shape.half_height - 0.05);
+    let filter = SpatialQueryFilter::from_excluded_entities([entity]);
+
+    // something in front of the feet too steep to walk up, but nothing at step height
+    let Some(blocking) = spatial_query.cast_ray(feet, direction, reach, true, filter.clone()) else {return;};
+
+    if blocking.normal.angle_between(up) <= max_slope_angle.0 {return;}
+
+    let above = feet + up * max_step_height.0;
+
+    if spatial_query.cast_ray(above, direction, reach, true, filter.clone()).is_some() {return;}
+
+    // the top of the step has to be something the player can stand on
+    let Some(hit) = spatial_query.cast_ray(
+        above + *direction * reach,
+        down,
+        max_step_height.0,
+        true,
+        filter
+    ) else {return;};
+
+    if hit.normal.angle_between(up) > max_slope_angle.0 {return;}
+
+    transform.translation += up * (max_step_height.0 - hit.time_of_impact + 0.05);
+}
@@ -1,7 +1,8 @@
 use bevy::prelude::*;
 use bevy_xpbd_3d::{math::*, prelude::*};
+use serde::{Deserialize, Serialize};
 
-use crate::{player_animation::PlayerAnimationPlugin, player_input::{PlayerInputPlugin, Swing}, player_movement::PlayerMovementPlugin, weapons::WeaponPlugin};
+use crate::{movement_tuning::{MovementTuning, MovementTuningPlugin}, packs::{PackPlugin, PackUpgrades}, player_animation::PlayerAnimationPlugin, player_gravity::PlayerGravityPlugin, player_input::{PlayerInputPlugin, Swing}, player_movement::PlayerMovementPlugin, player_state::{PlayerState, PlayerStatePlugin}, surface_effects::SurfaceEffectsPlugin, surfaces::Surface, weapons::{Grapple, WeaponPlugin}};
 
 pub struct PlayerPlugin;
 
@@ -10,10 +11,15 @@ impl Plugin for PlayerPlugin {
         app
             .add_event::<MovementAction>()
             .add_plugins((
+                MovementTuningPlugin,
+                PlayerStatePlugin,
                 PlayerInputPlugin,
                 PlayerMovementPlugin,
                 PlayerAnimationPlugin,
-                WeaponPlugin
+                WeaponPlugin,
+                PackPlugin,
+                PlayerGravityPlugin,
+                SurfaceEffectsPlugin
             
             ))
             .add_systems(Startup, spawn_player);
@@ -22,17 +28,66 @@ impl Plugin for PlayerPlugin {
 }
 
 
-#[derive(Event)]
+#[derive(Event, Clone, Copy)]
 pub enum MovementAction {
     Walk(Vec2),
     Jump,
     DoubleJump,
-    Gliding,
+    // the way the player faces and how far the stick is pushed
+    Gliding(Vec2, f32),
+    // the push of the wind volumes the player is gliding through
+    Wind(Vec3),
     Sideflip(Vec2),
     Longjump(Vec2),
     Highjump1,
     Highjump2,
-    Swing(Vec2)
+    Swing(Vec2),
+    // the slope normal and the world direction the stick steers to
+    Slide(Vec3, Vec2),
+    // jumps away from the slope with this normal
+    SlideJump(Vec3),
+    WallSlide,
+    // jumps away from the wall with this normal
+    WallJump(Vec3),
+    // hanging still, or shimmying with this velocity
+    LedgeHang(Vec3),
+    // the velocity that keeps the player on the rail
+    Grind(Vec3),
+    // jumps off the rail towards this side, to land on the next one
+    RailHop(Vec3),
+    // reeled in by the swingshot with this velocity
+    Grapple(Vec3),
+    // how much the player accelerates in the water this step
+    Swim(Vec3),
+    // jumps out of the water from the surface
+    SwimJump,
+    // climbs along the wall with this normal, x to the right and y up
+    Climb(Vec3, Vec2),
+    // backflips off the wall with this normal
+    ClimbJump(Vec3)
+}
+
+impl MovementAction {
+    // the same action with its world directions turned, the stick directions
+    // are already in the plane the player walks on and stay as they are
+    pub fn rotated(self, rotation: Quat) -> Self {
+        use MovementAction::*;
+
+        match self {
+            Wind(force) => Wind(rotation * force),
+            Slide(normal, steering) => Slide(rotation * normal, steering),
+            SlideJump(normal) => SlideJump(rotation * normal),
+            WallJump(normal) => WallJump(rotation * normal),
+            LedgeHang(shimmy) => LedgeHang(rotation * shimmy),
+            Grind(velocity) => Grind(rotation * velocity),
+            RailHop(side) => RailHop(rotation * side),
+            Grapple(velocity) => Grapple(rotation * velocity),
+            Swim(acceleration) => Swim(rotation * acceleration),
+            Climb(normal, direction) => Climb(rotation * normal, direction),
+            ClimbJump(normal) => ClimbJump(rotation * normal),
+            action => action
+        }
+    }
 }
 
 pub enum Animation {
@@ -47,7 +102,12 @@ pub enum Animation {
     SideFlipR,
     Longjump,
     Highjump,
-    Swing
+    Swing,
+    Slide,
+    Grind,
+    Swim,
+    Dive,
+    Climb
 
 }
 
@@ -63,6 +123,10 @@ pub struct Gun;
 #[derive(Component)]
 pub struct Wrench;
 
+// identifier for the swingshot
+#[derive(Component)]
+pub struct Swingshot;
+
 
 // handle for the camera target
 #[derive(Component)]
@@ -84,13 +148,36 @@ pub struct CurrentAnimation(pub Animation);
 #[derive(Component)]
 pub struct PlayerDirection(pub Vec2);
 
+// what the player wants to do this frame, filled from the input devices
+// but it can be written by anything else (tests, AI, replays)
+#[derive(Component, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct PlayerIntent {
+    // left stick or WASD
+    pub movement: Vec2,
+    // how much the camera should look around this frame
+    pub look: Vec2,
+    pub jump_pressed: bool,
+    pub jump_held: bool,
+    pub crouch: bool,
+    pub strafe: bool,
+    pub fire: bool,
+    pub swing_pressed: bool,
+    // recordings made before the swingshot don't have these
+    #[serde(default)]
+    pub grapple_pressed: bool,
+    #[serde(default)]
+    pub grapple_held: bool,
+    #[serde(default)]
+    pub pack_pressed: bool
+}
+
 // [MOVEMENT COMPONENTS]
 
 // the player acceleration
 #[derive(Component)]
 pub struct MovementAcceleration(pub Scalar);
 
-// how much the player slows down
+// how fast the player slows down on the ground, per second
 #[derive(Component)]
 pub struct MovementDampingFactor(pub Scalar);
 
@@ -113,10 +200,161 @@ pub struct JumpCounter {
 #[derive(Component)]
 pub struct MaxSlopeAngle(pub Scalar);
 
-// the height value of the player the last time it was grounded
+// the highest step the player walks up without jumping
+#[derive(Component)]
+pub struct MaxStepHeight(pub Scalar);
+
+// how far below the ground caster the player is still pulled down to the ground,
+// so walking down stairs and slopes doesn't leave the ground
+#[derive(Component)]
+pub struct GroundSnapDistance(pub Scalar);
+
+// half the height of the collider and its radius, to find where the feet are
+#[derive(Component)]
+pub struct CharacterShape {
+    pub half_height: Scalar,
+    pub radius: Scalar,
+    pub standing_half_height: Scalar
+}
+
+impl CharacterShape {
+    // a capsule as wide as the player with this half height
+    pub fn capsule(&self, half_height: Scalar) -> Collider {
+        Collider::capsule((half_height - self.radius).max(0.) * 2., self.radius)
+    }
+}
+
+// how tall the player is while crouching
+#[derive(Component)]
+pub struct CrouchHeight(pub Scalar);
+
+// whether there's room above the player to stand up from a crouch
+#[derive(Component)]
+pub struct Headroom(pub bool);
+
+// the height of the player along its gravity the last time it was grounded
 #[derive(Component)]
 pub struct GroundedHeight(pub f32);
 
+// the normal of the ground under the player, straight up while in the air
+#[derive(Component)]
+pub struct GroundNormal(pub Vector);
+
+// the body the player is standing on and how fast the ground under its feet moves,
+// the velocity it had when the player left it is kept until the next landing
+#[derive(Component)]
+pub struct GroundVelocity {
+    pub entity: Option<Entity>,
+    pub velocity: Vector,
+    pub inherited: Vector
+}
+
+// the normal of the wall the player is touching, if any
+#[derive(Component)]
+pub struct WallNormal(pub Option<Vector>);
+
+// the ledge the player is hanging from, and the last time it let go of one
+#[derive(Component)]
+pub struct LedgeGrab {
+    pub point: Vector,
+    pub normal: Vector,
+    pub released_time: Scalar
+}
+
+// the wall the player is climbing, and the last time it let go of one
+#[derive(Component)]
+pub struct Climbing {
+    pub normal: Vector,
+    pub released_time: Scalar
+}
+
+// which way gravity pulls the character, and how far it's leaned over from upright to match,
+// the tilt is carried along as gravity turns so it never flips when up goes past sideways
+#[derive(Component)]
+pub struct GravityDirection {
+    pub down: Vector,
+    pub tilt: Quat
+}
+
+impl GravityDirection {
+    pub fn up(&self) -> Vector {
+        -self.down
+    }
+
+    // turns an upright direction into the one it is under this gravity
+    pub fn frame(&self) -> Quat {
+        self.tilt
+    }
+
+    // turns gravity to a new down, and the tilt by as much as gravity turned
+    pub fn turn_to(&mut self, down: Vector) {
+        self.tilt = (Quat::from_rotation_arc(self.down, down) * self.tilt).normalize();
+        self.down = down;
+    }
+
+    // how high a point is along this gravity
+    pub fn height(&self, point: Vector) -> Scalar {
+        point.dot(self.up())
+    }
+
+    // the part of a direction that runs along the ground under this gravity
+    pub fn flatten(&self, direction: Vector) -> Vector {
+        direction - self.up() * direction.dot(self.up())
+    }
+
+    // the heading on the ground a direction has for a player standing upright
+    pub fn heading(&self, direction: Vector) -> Vec2 {
+        let upright = self.frame().inverse() * direction;
+
+        Vec2::new(upright.x, upright.z)
+    }
+
+    // and back, the direction a heading on the ground points to under this gravity
+    pub fn from_heading(&self, heading: Vec2) -> Vector {
+        self.frame() * Vector::new(heading.x, 0., heading.y)
+    }
+}
+
+// what the ground the player last stood on is made of,
+// and how far it has walked on it since its last footstep
+#[derive(Component)]
+pub struct GroundSurface {
+    pub surface: Surface,
+    pub walked: Scalar
+}
+
+// how many seconds of air the player has left underwater
+#[derive(Component)]
+pub struct Oxygen(pub Scalar);
+
+// the rail the player is grinding on or left last, how far along it,
+// which way it's going and how fast
+#[derive(Component)]
+pub struct RailGrind {
+    pub rail: Option<Entity>,
+    pub distance: Scalar,
+    pub direction: Scalar,
+    pub speed: Scalar,
+    pub released_time: Scalar
+}
+
+// remembers jump and swing presses for a short window so
+// pressing a few frames too early isn't lost
+#[derive(Component)]
+pub struct InputBuffer {
+    pub window: Scalar,
+    pub jump_time: Option<Scalar>,
+    pub swing_time: Option<Scalar>,
+    pub grapple_time: Option<Scalar>
+}
+
+// lets the player act as grounded for a short window after walking off a ledge
+#[derive(Component)]
+pub struct CoyoteTime {
+    pub window: Scalar,
+    pub grounded_time: Scalar
+}
+
 // [BUNDLES]
 #[derive(Bundle)]
 pub struct CharacterControllerBundle {
@@ -124,14 +362,31 @@ pub struct CharacterControllerBundle {
     rigid_body: RigidBody,
     collider: Collider,
     ground_caster: ShapeCaster,
+    shape: CharacterShape,
+    crouch_height: CrouchHeight,
+    headroom: Headroom,
+    max_step_height: MaxStepHeight,
+    ground_snap_distance: GroundSnapDistance,
     locked_axes: LockedAxes,
     movement: MovementBundle,
+    input_buffer: InputBuffer,
+    coyote_time: CoyoteTime,
+    ground_normal: GroundNormal,
+    ground_velocity: GroundVelocity,
+    wall_normal: WallNormal,
+    ledge_grab: LedgeGrab,
+    rail_grind: RailGrind,
+    climbing: Climbing,
+    gravity_direction: GravityDirection,
+    ground_surface: GroundSurface,
 }
 impl CharacterControllerBundle {
     fn new(collider: Collider) -> Self {
         let mut caster_shape = collider.clone();
         caster_shape.set_scale(Vector::ONE * 0.99, 10);
 
+        let half_extents = collider.shape().compute_local_aabb().half_extents();
+
         Self {
             character_controller: CharacterController,
             rigid_body: RigidBody::Dynamic,
@@ -143,8 +398,59 @@ impl CharacterControllerBundle {
                 Direction3d::NEG_Y,
             )
             .with_max_time_of_impact(0.2),
+            shape: CharacterShape {
+                half_height: half_extents.y,
+                radius: half_extents.x,
+                standing_half_height: half_extents.y
+            },
+            crouch_height: CrouchHeight(half_extents.y * 1.4),
+            headroom: Headroom(true),
+            max_step_height: MaxStepHeight(0.35),
+            ground_snap_distance: GroundSnapDistance(0.3),
+            // the physics never turns the player, it's leaned over to its gravity by hand
             locked_axes: LockedAxes::ROTATION_LOCKED,
             movement: MovementBundle::default(),
+            input_buffer: InputBuffer {
+                window: 0.15,
+                jump_time: None,
+                swing_time: None,
+                grapple_time: None
+            },
+            coyote_time: CoyoteTime {
+                window: 0.12,
+                grounded_time: -1.
+            },
+            ground_normal: GroundNormal(Vector::Y),
+            ground_velocity: GroundVelocity {
+                entity: None,
+                velocity: Vector::ZERO,
+                inherited: Vector::ZERO
+            },
+            wall_normal: WallNormal(None),
+            ledge_grab: LedgeGrab {
+                point: Vector::ZERO,
+                normal: Vector::ZERO,
+                released_time: -1.
+            },
+            rail_grind: RailGrind {
+                rail: None,
+                distance: 0.,
+                direction: 1.,
+                speed: 0.,
+                released_time: -1.
+            },
+            climbing: Climbing {
+                normal: Vector::ZERO,
+                released_time: -1.
+            },
+            gravity_direction: GravityDirection {
+                down: Vector::NEG_Y,
+                tilt: Quat::IDENTITY
+            },
+            ground_surface: GroundSurface {
+                surface: Surface::Default,
+                walked: 0.
+            },
         }
     }
 
@@ -159,6 +465,31 @@ impl CharacterControllerBundle {
         self.movement = MovementBundle::new(acceleration, damping, jump_impulse, double_jump_impulse, max_slope_angle);
         self
     }
+
+    pub fn with_input_windows(
+        mut self,
+        input_buffer: Scalar,
+        coyote_time: Scalar,
+    ) -> Self {
+        self.input_buffer.window = input_buffer;
+        self.coyote_time.window = coyote_time;
+        self
+    }
+
+    pub fn with_step_handling(
+        mut self,
+        max_step_height: Scalar,
+        ground_snap_distance: Scalar,
+    ) -> Self {
+        self.max_step_height.0 = max_step_height;
+        self.ground_snap_distance.0 = ground_snap_distance;
+        self
+    }
+
+    pub fn with_crouch_height(mut self, crouch_height: Scalar) -> Self {
+        self.crouch_height.0 = crouch_height;
+        self
+    }
 }
 
 
@@ -196,7 +527,7 @@ impl MovementBundle {
 
 impl Default for MovementBundle {
     fn default() -> Self {
-        Self::new(30.0, 0.9, 7.0, 7.0,  PI * 0.45)
+        Self::new(30.0, 6.3, 7.0, 7.0,  PI * 0.45)
     }
 }
 
@@ -210,6 +541,7 @@ impl Default for MovementBundle {
 fn spawn_player(
     mut commands: Commands,
     assets: Res<AssetServer>,
+    tuning: Res<MovementTuning>,
 ) {
 
     commands.insert_resource(PlayerAnimations(vec![
@@ -227,6 +559,11 @@ fn spawn_player(
         assets.load("ratchet2.glb#Animation11"),
         assets.load("ratchet2.glb#Animation12"),
         assets.load("ratchet2.glb#Animation13"),
+        assets.load("grind.glb#Animation0"),
+        assets.load("swim.glb#Animation0"),
+        assets.load("swim.glb#Animation1"),
+        assets.load("climb.glb#Animation0"),
+        assets.load("slide.glb#Animation0"),
         // other animations here
     ]));
 
@@ -239,22 +576,36 @@ fn spawn_player(
         },
         
         CharacterControllerBundle::new(Collider::capsule(0.4, 0.4)).with_movement(
-            65.0,
-            0.92,
-            11.2,
-            9.,
-            (45.0 as Scalar).to_radians(),
-        ),
+            tuning.acceleration,
+            tuning.damping,
+            tuning.jump_impulse,
+            tuning.double_jump_impulse,
+            tuning.max_slope_angle.to_radians(),
+        ).with_input_windows(
+            tuning.input_buffer,
+            tuning.coyote_time,
+        ).with_step_handling(
+            tuning.max_step_height,
+            tuning.ground_snap_distance,
+        ).with_crouch_height(tuning.crouch_height),
         Friction::ZERO.with_combine_rule(CoefficientCombine::Min),
         Restitution::ZERO.with_combine_rule(CoefficientCombine::Min),
-        GravityScale(3.0),
+        GravityScale(tuning.gravity_scale),
         CameraTarget,
         CurrentAnimation(Animation::Idle),
+        PlayerState::Fall,
         PlayerDirection(Vec2::ZERO),
+        PlayerIntent::default(),
         Swing {
             swing_time: 0.,
             swing_number: 0
         },
+        Grapple {
+            target: None,
+            joint: None
+        },
+        PackUpgrades::default(),
+        Oxygen(tuning.oxygen_time),
         GroundedHeight(0.)
         
     )).id();
@@ -302,5 +653,18 @@ fn spawn_player(
     )).id();
 
     commands.entity(player).push_children(&[gun]);
+
+
+    let swingshot = commands.spawn((
+        SceneBundle {
+            scene: assets.load("swingshot.glb#Scene0"),
+            transform: Transform::from_xyz(0.155, 0.06, -0.33).with_scale(Vec3::splat(0.12)),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        Swingshot
+    )).id();
+
+    commands.entity(player).push_children(&[swingshot]);
 }
 
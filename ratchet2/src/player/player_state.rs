@@ -0,0 +1,420 @@
+use bevy::{audio::{PlaybackMode, Volume}, prelude::*};
+use bevy_xpbd_3d::prelude::*;
+
+use crate::{movement_tuning::MovementTuning, player::{CharacterController, CharacterShape, CrouchHeight, GravityDirection, GroundVelocity, JumpCounter}};
+
+pub struct PlayerStatePlugin;
+
+impl Plugin for PlayerStatePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<StateChanged>();
+    }
+}
+
+// what the player is doing, there's always exactly one
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PlayerState {
+    // on the ground
+    Idle,
+    Walk,
+    Strafe,
+    Crouch,
+    Land,
+    Swing,
+
+    // in the air
+    Jump,
+    DoubleJump,
+    SideflipL,
+    SideflipR,
+    Longjump,
+    Highjump,
+    Glide,
+    Fall,
+    Slide,
+    WallSlide,
+    LedgeHang,
+    Grind,
+    Swingshot,
+    Climb,
+
+    // in the water
+    Swim,
+    Dive
+}
+
+impl PlayerState {
+    // the transition table, every state the player can go to from this one
+    pub fn transitions(self) -> &'static [PlayerState] {
+        use PlayerState::*;
+
+        match self {
+            Idle => &[Walk, Strafe, Crouch, Swing, Jump, Fall, Slide, Swingshot, Swim, Climb],
+            Walk => &[Idle, Strafe, Crouch, Swing, Jump, Fall, Slide, Swingshot, Swim, Climb],
+            Strafe => &[Idle, Walk, Crouch, Swing, Jump, SideflipL, SideflipR, Fall, Slide, Swingshot, Swim],
+            Land => &[Idle, Walk, Strafe, Crouch, Swing, Jump, Fall, Slide, Swingshot, Swim],
+            Crouch => &[Idle, Swing, SideflipL, SideflipR, Longjump, Highjump, Fall, Slide, Swim],
+            Swing => &[Idle, Fall, Slide, Swim],
+
+            // coming down on a steep slope starts sliding straight away
+            Jump => &[DoubleJump, WallSlide, LedgeHang, Grind, Swingshot, Climb, Fall, Land, Slide, Swim],
+            DoubleJump => &[WallSlide, LedgeHang, Grind, Swingshot, Climb, Fall, Land, Slide, Swim],
+            SideflipL | SideflipR => &[WallSlide, LedgeHang, Grind, Swingshot, Climb, Fall, Land, Slide, Swim],
+            Longjump | Highjump => &[WallSlide, LedgeHang, Grind, Swingshot, Climb, Fall, Land, Slide, Swim],
+            Glide => &[DoubleJump, WallSlide, LedgeHang, Grind, Swingshot, Climb, Fall, Land, Slide, Swim],
+            // coyote time lets the player act as grounded for a moment after falling off a ledge
+            Fall => &[Jump, DoubleJump, Crouch, Swing, SideflipL, SideflipR, Glide, Slide, WallSlide, LedgeHang, Grind, Swingshot, Climb, Land, Swim],
+            Slide => &[Jump, Fall, Land, Swim],
+            WallSlide => &[Jump, LedgeHang, Swingshot, Climb, Fall, Land, Swim],
+            // climbing up and letting go both go through falling
+            LedgeHang => &[Fall, Land],
+            // hopping to another rail is a jump too
+            Grind => &[Jump, Fall],
+            // letting go keeps the momentum of the swing
+            Swingshot => &[Fall, Land],
+            // climbing over the top puts the player straight on its feet
+            Climb => &[Jump, Fall, Idle],
+            // jumping out of the water is only possible from the surface
+            Swim => &[Dive, Jump, Fall],
+            Dive => &[Swim, Fall]
+        }
+    }
+
+    pub fn can_transition(self, to: PlayerState) -> bool {
+        self.transitions().contains(&to)
+    }
+
+    pub fn is_grounded(self) -> bool {
+        use PlayerState::*;
+
+        matches!(self, Idle | Walk | Strafe | Crouch | Land | Swing)
+    }
+
+    pub fn is_swimming(self) -> bool {
+        matches!(self, PlayerState::Swim | PlayerState::Dive)
+    }
+
+    // how hard gravity pulls in this state, hanging on, grinding, climbing and swimming the player doesn't fall,
+    // the highjump changes it again halfway through on its own
+    pub fn gravity_scale(self, tuning: &MovementTuning) -> f32 {
+        use PlayerState::*;
+
+        match self {
+            LedgeHang | Grind | Climb | Swim | Dive => 0.,
+            Longjump => tuning.longjump_gravity_scale,
+            Highjump => tuning.highjump_gravity_scale,
+            _ => tuning.gravity_scale
+        }
+    }
+
+    // the states that can turn into strafing when the strafe button is held
+    pub fn allows_strafe(self) -> bool {
+        use PlayerState::*;
+
+        matches!(self, Idle | Walk | Strafe | Land | Jump | DoubleJump | Fall)
+    }
+}
+
+// sent every time the player goes from one state to another
+#[derive(Event, Clone, Copy)]
+pub struct StateChanged {
+    pub entity: Entity,
+    pub from: PlayerState,
+    pub to: PlayerState
+}
+
+// identifier for the glide audio player
+#[derive(Component)]
+pub struct GlideAudio;
+
+// identifier for the grind audio player
+#[derive(Component)]
+pub struct GrindAudio;
+
+
+// moves the player to another state if the transition table allows it,
+// returns whether the player is in that state afterwards
+pub fn change_state(
+    entity: Entity,
+    state: &mut PlayerState,
+    to: PlayerState,
+    state_changed: &mut EventWriter<StateChanged>,
+) -> bool {
+    if *state == to {
+        return true;
+    }
+
+    if !state.can_transition(to) {
+        return false;
+    }
+
+    state_changed.send(StateChanged {
+        entity,
+        from: *state,
+        to
+    });
+
+    *state = to;
+
+    true
+}
+
+// what happens when the player leaves a state and enters the next one
+pub fn run_state_hooks(
+    mut state_changed: EventReader<StateChanged>,
+    mut player: Query<(
+        &mut JumpCounter,
+        &mut GravityScale,
+        &mut GroundVelocity,
+        &mut CharacterShape,
+        &CrouchHeight,
+        &mut Collider,
+        &mut ShapeCaster,
+        &mut Transform,
+        &GravityDirection
+    ), With<CharacterController>>,
+    glide_audio: Query<Entity, With<GlideAudio>>,
+    grind_audio: Query<Entity, With<GrindAudio>>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    tuning: Res<MovementTuning>,
+) {
+    for change in state_changed.read() {
+        let Ok((
+            mut jump_counter,
+            mut gravity_scale,
+            mut ground_velocity,
+            mut shape,
+            crouch_height,
+            mut collider,
+            mut ground_caster,
+            mut transform,
+            gravity_direction
+        )) = player.get_mut(change.entity) else {continue;};
+
+        // exit
+        match change.from {
+            PlayerState::Grind => {
+                for audio in grind_audio.iter() {
+                    commands.entity(audio).despawn();
+                }
+            }
+            PlayerState::Glide => {
+                for audio in glide_audio.iter() {
+                    commands.entity(audio).despawn();
+                }
+            }
+            PlayerState::Crouch => {
+                let half_height = shape.standing_half_height;
+                resize(&mut shape, half_height, &mut collider, &mut ground_caster, &mut transform, gravity_direction.up());
+            }
+            _ => {}
+        }
+
+        gravity_scale.0 = change.to.gravity_scale(&tuning);
+
+        // the momentum of a platform lasts until the player touches something again
+        if matches!(change.to, PlayerState::Land | PlayerState::Slide | PlayerState::WallSlide | PlayerState::LedgeHang | PlayerState::Grind | PlayerState::Climb | PlayerState::Swim) {
+            ground_velocity.inherited = Vec3::ZERO;
+        }
+
+        // the water holds the player up, swimming and diving alike
+        if change.to.is_swimming() && !change.from.is_swimming() {
+            jump_counter.counter = 0.;
+
+            commands.spawn((
+                AudioBundle {
+                    source: asset_server.load("splash.ogg"),
+                    settings: PlaybackSettings {
+                        volume: Volume::new(0.07),
+                        mode: PlaybackMode::Despawn,
+                        ..default()
+                    }
+                },
+            ));
+        }
+
+        // enter
+        match change.to {
+            PlayerState::Land => {
+                jump_counter.counter = 0.;
+            }
+            PlayerState::Crouch => {
+                resize(&mut shape, crouch_height.0 * 0.5, &mut collider, &mut ground_caster, &mut transform, gravity_direction.up());
+            }
+            // a jump off a wall the player is climbing counts as the first one
+            PlayerState::Climb => {
+                jump_counter.counter = 0.;
+            }
+            // a jump off the rail counts as the first one
+            PlayerState::Grind => {
+                jump_counter.counter = 0.;
+
+                commands.spawn((
+                    AudioBundle {
+                        source: asset_server.load("grind.ogg"),
+                        settings: PlaybackSettings {
+                            volume: Volume::new(0.07),
+                            mode: PlaybackMode::Loop,
+                            ..default()
+                        }
+                    },
+                    GrindAudio
+                ));
+            }
+            PlayerState::Glide => {
+                commands.spawn((
+                    AudioBundle {
+                        source: asset_server.load("glide.ogg"),
+                        settings: PlaybackSettings {
+                            volume: Volume::new(0.07),
+                            speed: 0.75,
+                            mode: PlaybackMode::Loop,
+                            ..default()
+                        }
+                    },
+                    GlideAudio
+                ));
+            }
+            _ => {}
+        }
+    }
+}
+
+// changes the height of the player collider and of its ground caster,
+// the feet stay where they are
+fn resize(
+    shape: &mut CharacterShape,
+    half_height: f32,
+    collider: &mut Collider,
+    ground_caster: &mut ShapeCaster,
+    transform: &mut Transform,
+    up: Vec3
+) {
+    transform.translation += up * (half_height - shape.half_height);
+    shape.half_height = half_height;
+
+    *collider = shape.capsule(half_height);
+
+    let mut caster_shape = collider.clone();
+    caster_shape.set_scale(Vec3::ONE * 0.99, 10);
+    ground_caster.shape = caster_shape;
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::SystemState;
+
+    use super::*;
+
+    fn world_with_events() -> World {
+        let mut world = World::new();
+        world.init_resource::<Events<StateChanged>>();
+        world
+    }
+
+    #[test]
+    fn jumping_cannot_go_straight_to_idle() {
+        assert!(!PlayerState::Jump.can_transition(PlayerState::Idle));
+        assert!(PlayerState::Jump.can_transition(PlayerState::Land));
+    }
+
+    #[test]
+    fn rejected_transition_keeps_the_state_and_sends_nothing() {
+        let mut world = world_with_events();
+        let entity = world.spawn_empty().id();
+
+        let mut system_state: SystemState<EventWriter<StateChanged>> = SystemState::new(&mut world);
+        let mut state_changed = system_state.get_mut(&mut world);
+
+        let mut state = PlayerState::Jump;
+        assert!(!change_state(entity, &mut state, PlayerState::Idle, &mut state_changed));
+        assert_eq!(state, PlayerState::Jump);
+
+        assert!(world.resource::<Events<StateChanged>>().is_empty());
+    }
+
+    #[test]
+    fn repeating_a_state_sends_no_event() {
+        let mut world = world_with_events();
+        let entity = world.spawn_empty().id();
+
+        let mut system_state: SystemState<EventWriter<StateChanged>> = SystemState::new(&mut world);
+        let mut state_changed = system_state.get_mut(&mut world);
+
+        let mut state = PlayerState::Crouch;
+        assert!(change_state(entity, &mut state, PlayerState::Crouch, &mut state_changed));
+        assert_eq!(state, PlayerState::Crouch);
+
+        assert!(world.resource::<Events<StateChanged>>().is_empty());
+    }
+
+    #[test]
+    fn allowed_transition_sends_one_event() {
+        let mut world = world_with_events();
+        let entity = world.spawn_empty().id();
+
+        let mut system_state: SystemState<EventWriter<StateChanged>> = SystemState::new(&mut world);
+        let mut state_changed = system_state.get_mut(&mut world);
+
+        let mut state = PlayerState::Fall;
+        assert!(change_state(entity, &mut state, PlayerState::Land, &mut state_changed));
+        assert_eq!(state, PlayerState::Land);
+
+        let events = world.resource::<Events<StateChanged>>();
+        assert_eq!(events.len(), 1);
+
+        let change = events.iter_current_update_events().next().unwrap();
+        assert_eq!((change.entity, change.from, change.to), (entity, PlayerState::Fall, PlayerState::Land));
+    }
+
+    #[test]
+    fn landing_resets_the_jump_counter() {
+        let mut app = App::new();
+        app
+            .add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .add_event::<StateChanged>()
+            .insert_resource(MovementTuning::default())
+            .add_systems(Update, run_state_hooks);
+
+        let shape = CharacterShape {
+            half_height: 0.8,
+            radius: 0.4,
+            standing_half_height: 0.8
+        };
+        let collider = shape.capsule(shape.half_height);
+
+        let player = app.world.spawn((
+            CharacterController,
+            JumpCounter {
+                counter: 2.,
+                jump_time: 0.
+            },
+            GravityScale(1.),
+            GroundVelocity {
+                entity: None,
+                velocity: Vec3::ZERO,
+                inherited: Vec3::ZERO
+            },
+            shape,
+            CrouchHeight(0.85),
+            collider.clone(),
+            ShapeCaster::new(collider, Vec3::ZERO, Quat::default(), Direction3d::NEG_Y),
+            Transform::default(),
+            GravityDirection {
+                down: Vec3::NEG_Y,
+                tilt: Quat::IDENTITY
+            }
+        )).id();
+
+        app.world.send_event(StateChanged {
+            entity: player,
+            from: PlayerState::DoubleJump,
+            to: PlayerState::Land
+        });
+
+        app.update();
+
+        assert_eq!(app.world.get::<JumpCounter>(player).unwrap().counter, 0.);
+    }
+}
@@ -0,0 +1,126 @@
+use bevy::{audio::{PlaybackMode, Volume}, prelude::*};
+use bevy_xpbd_3d::prelude::LinearVelocity;
+
+use crate::{movement_tuning::MovementTuning, player::*, player_controls::playing, player_movement::decay, player_state::{run_state_hooks, PlayerState, StateChanged}};
+
+pub struct SurfaceEffectsPlugin;
+
+impl Plugin for SurfaceEffectsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(FixedUpdate, (
+                footsteps,
+                landing_dust
+            ).after(run_state_hooks).run_if(playing))
+            .add_systems(Update, settle_dust);
+    }
+}
+
+// how many puffs a landing kicks up and how long they last
+const DUST_PUFFS: usize = 6;
+const DUST_LIFETIME: f32 = 0.45;
+
+// a puff of whatever the player landed on, it spreads out and shrinks away
+#[derive(Component)]
+pub struct Dust {
+    pub velocity: Vec3,
+    pub age: f32
+}
+
+
+// a footstep every stride while walking, sounding like the ground under the player
+fn footsteps(
+    mut player: Query<(&PlayerState, &LinearVelocity, &mut GroundSurface), With<CharacterController>>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    tuning: Res<MovementTuning>,
+    time: Res<Time>
+) {
+    let Ok((state, velocity, mut ground_surface)) = player.get_single_mut() else {return;};
+
+    // the first step comes half a stride after starting to walk
+    if !matches!(state, PlayerState::Walk | PlayerState::Strafe) {
+        ground_surface.walked = tuning.footstep_stride * 0.5;
+        return;
+    }
+
+    ground_surface.walked += velocity.length() * time.delta_seconds();
+
+    if ground_surface.walked < tuning.footstep_stride {return;}
+    ground_surface.walked -= tuning.footstep_stride;
+
+    commands.spawn((
+        AudioBundle {
+            source: asset_server.load(ground_surface.surface.footstep_sound()),
+            settings: PlaybackSettings {
+                volume: Volume::new(0.05),
+                mode: PlaybackMode::Despawn,
+                ..default()
+            }
+        },
+    ));
+}
+
+// landing kicks up a ring of dust around the feet, colored like the ground
+fn landing_dust(
+    mut state_changed: EventReader<StateChanged>,
+    player: Query<(&Transform, &CharacterShape, &GravityDirection, &GroundSurface), With<CharacterController>>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for change in state_changed.read() {
+        if change.to != PlayerState::Land {continue;}
+
+        let Ok((transform, shape, gravity_direction, ground_surface)) = player.get(change.entity) else {continue;};
+
+        let up = gravity_direction.up();
+        let feet = transform.translation - up * shape.half_height;
+
+        let mesh = meshes.add(Sphere::new(0.08));
+        let material = materials.add(StandardMaterial {
+            base_color: ground_surface.surface.dust_color(),
+            unlit: true,
+            ..default()
+        });
+
+        for puff in 0..DUST_PUFFS {
+            let angle = puff as f32 / DUST_PUFFS as f32 * std::f32::consts::TAU;
+            let outwards = gravity_direction.frame() * Vec3::new(angle.cos(), 0., angle.sin());
+
+            commands.spawn((
+                PbrBundle {
+                    mesh: mesh.clone(),
+                    material: material.clone(),
+                    transform: Transform::from_translation(feet + outwards * shape.radius),
+                    ..default()
+                },
+                Dust {
+                    velocity: outwards * 2.5 + up * 0.6,
+                    age: 0.
+                }
+            ));
+        }
+    }
+}
+
+fn settle_dust(
+    mut dust: Query<(Entity, &mut Dust, &mut Transform)>,
+    mut commands: Commands,
+    time: Res<Time>,
+) {
+    for (entity, mut puff, mut transform) in dust.iter_mut() {
+        puff.age += time.delta_seconds();
+
+        if puff.age >= DUST_LIFETIME {
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        transform.translation += puff.velocity * time.delta_seconds();
+        transform.scale = Vec3::splat(1. + puff.age / DUST_LIFETIME) * (1. - puff.age / DUST_LIFETIME);
+
+        // the air slows the puffs down quickly
+        puff.velocity *= decay(6., &time);
+    }
+}
@@ -1,7 +1,7 @@
-use bevy::{math::vec3, prelude::*};
-use bevy_xpbd_3d::plugins::collision::{Collider, Collisions};
+use bevy::{audio::{PlaybackMode, Volume}, math::vec3, prelude::*};
+use bevy_xpbd_3d::prelude::{Collider, Collisions, DistanceJoint, Joint, SpatialQuery, SpatialQueryFilter};
 
-use crate::{level::LevelCollider, player::{CharacterController, Gun, MovementAction, Wrench}, player_input::{Grounded, Swing}};
+use crate::{camera::CameraIdentifier, grapple_points::{GrappleMode, GrapplePoint}, level::LevelCollider, movement_tuning::MovementTuning, player::{CharacterController, CoyoteTime, GravityDirection, GroundedHeight, Gun, Headroom, InputBuffer, JumpCounter, MovementAction, PlayerIntent, Swingshot, Wrench}, player_controls::playing, player_input::{in_coyote_time, Swing}, player_movement::movement, player_state::{change_state, run_state_hooks, PlayerState, StateChanged}};
 
 pub struct WeaponPlugin;
 
@@ -13,14 +13,28 @@ pub struct Bullet {
     pub shoot_time: f32
 }
 
+// the grapple point the swingshot is latched onto and the rope joint when swinging
+#[derive(Component)]
+pub struct Grapple {
+    pub target: Option<Entity>,
+    pub joint: Option<Entity>
+}
+
 impl Plugin for WeaponPlugin {
     fn build(&self, app: &mut App) {
         app
         .add_systems(Update, (
             shoot, 
             update_bullet_pos,
-            swing
-        ));
+            draw_swingshot_rope
+        ).run_if(playing))
+        // the swing and the swingshot push the player, so they run with the rest of the character
+        // in a fixed order for the replays, after the ground check so the swingshot isn't let go
+        // on the ground it fired from
+        .add_systems(FixedUpdate, (
+            swing,
+            swingshot
+        ).chain().after(run_state_hooks).before(movement).run_if(playing));
     }
 }
 
@@ -29,8 +43,7 @@ fn shoot(
     mut gun: Query<&mut Visibility, (With<Gun>, Without<Wrench>)>,
     mut wrench: Query<&mut Visibility, With<Wrench>>,
     shots: Query<&Bullet>,
-    gamepads: Res<Gamepads>,
-    buttons: Res<ButtonInput<GamepadButton>>,
+    intent: Query<&PlayerIntent, With<CharacterController>>,
     time: Res<Time>,
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
@@ -38,67 +51,60 @@ fn shoot(
 
 ) {
     let Ok(mut gun_visibility) = gun.get_single_mut() else {return;};
+    let Ok(intent) = intent.get_single() else {return;};
     let Ok(mut wrench_visibility) = wrench.get_single_mut() else {return;};
 
     let Ok(transform) = player.get_single() else {return;};
 
 
-    for gamepad in gamepads.iter() {
-        let fire = GamepadButton {
-            gamepad,
-            button_type: GamepadButtonType::East
-        };
+    let mut last_shot_time = 0.;
 
+    for shot in shots.iter() {
+        if shot.shoot_time > last_shot_time {
+            last_shot_time = shot.shoot_time;
+        }
+    }
 
-        let mut last_shot_time = 0.;
 
-        for shot in shots.iter() {
-            if shot.shoot_time > last_shot_time {
-                last_shot_time = shot.shoot_time;
-            }
-        }
+    if intent.fire && last_shot_time + 0.1 < time.elapsed_seconds()  {
+        let direction = vec3(
+            transform.forward().x,
+            transform.forward().y,
+            transform.forward().z, 
+        );
 
+        *wrench_visibility = Visibility::Hidden; 
 
-        if buttons.pressed(fire) && last_shot_time + 0.1 < time.elapsed_seconds()  {
-            let direction = vec3(
-                transform.forward().x,
-                transform.forward().y,
-                transform.forward().z, 
-            );
+        *gun_visibility = Visibility::Visible;
+
+        let gun_position = Vec3::new(
+            transform.translation.x + 0.155,
+            transform.translation.y + 0.06,
+            transform.translation.z + 0.23
+        ) + direction * 1.5;
 
-            *wrench_visibility = Visibility::Hidden; 
-
-            *gun_visibility = Visibility::Visible;
-
-            let gun_position = Vec3::new(
-                transform.translation.x + 0.155,
-                transform.translation.y + 0.06,
-                transform.translation.z + 0.23
-            ) + direction * 1.5;
-
-            commands.spawn((
-                PbrBundle {
-                    mesh: meshes.add(Sphere::new(0.1)),
-                    material: materials.add(StandardMaterial {
-                        base_color: Color::YELLOW,
-                        ..default()
-                    }),
-                    transform: Transform::from_xyz(
-                        gun_position.x, 
-                        gun_position.y, 
-                        gun_position.z
-                    ),
+        commands.spawn((
+            PbrBundle {
+                mesh: meshes.add(Sphere::new(0.1)),
+                material: materials.add(StandardMaterial {
+                    base_color: Color::YELLOW,
                     ..default()
-                },
-                Collider::sphere(0.6),
-                Bullet {
-                    direction: direction,
-                    shoot_time: time.elapsed_seconds()
-                }
-                
-            ));
-        } 
-    }
+                }),
+                transform: Transform::from_xyz(
+                    gun_position.x, 
+                    gun_position.y, 
+                    gun_position.z
+                ),
+                ..default()
+            },
+            Collider::sphere(0.6),
+            Bullet {
+                direction: direction,
+                shoot_time: time.elapsed_seconds()
+            }
+            
+        ));
+    } 
 
 
 }
@@ -128,57 +134,216 @@ fn update_bullet_pos(
 }
 
 fn swing(
-    mut player: Query<(&mut Swing, Has<Grounded>, &Transform), With<CharacterController>>,
+    mut player: Query<(
+        Entity,
+        &mut Swing,
+        &mut PlayerState,
+        &Transform,
+        &mut InputBuffer,
+        &CoyoteTime,
+        &JumpCounter,
+        &Headroom
+    ), With<CharacterController>>,
     mut gun: Query<&mut Visibility, (With<Gun>, Without<Wrench>)>,
     mut wrench: Query<&mut Visibility, With<Wrench>>,
     mut movement_event: EventWriter<MovementAction>,
-    gamepads: Res<Gamepads>,
-    buttons: Res<ButtonInput<GamepadButton>>,
+    mut state_changed: EventWriter<StateChanged>,
     time: Res<Time>,
 ) {
-    let Ok((mut swing, grounded, transfom)) = player.get_single_mut() else {return;};
+    let Ok((player, mut swing, mut state, transfom, mut input_buffer, coyote_time, jump_counter, headroom)) = player.get_single_mut() else {return;};
     let Ok(mut gun_visibility) = gun.get_single_mut() else {return;};
     let Ok(mut wrench_visibility) = wrench.get_single_mut() else {return;};
 
 
-    for gamepad in gamepads.iter() {
-        let swing_button = GamepadButton {
-            gamepad,
-            button_type: GamepadButtonType::West
-        };
+    // swinging stands the player up, a low ceiling doesn't let it
+    if (state.is_grounded() || in_coyote_time(coyote_time, jump_counter, &time)) && headroom.0 {
+        // a press buffered during the previous swing chains the combo
+        if input_buffer.swing_time.is_some()
+        && swing.swing_number < 3
+        && swing.swing_time + 0.25 < time.elapsed_seconds()
+        && change_state(player, &mut state, PlayerState::Swing, &mut state_changed) {
 
+            input_buffer.swing_time = None;
 
+            *gun_visibility = Visibility::Hidden;
+            *wrench_visibility = Visibility::Visible; 
 
+            swing.swing_number += 1;
+            swing.swing_time = time.elapsed_seconds();
+            
+            let direction = Vec2::new(
+                transfom.forward().x, 
+                transfom.forward().z, 
+            );
 
-        if grounded {
-            if buttons.just_pressed(swing_button) 
-            && swing.swing_number < 3
-            && swing.swing_time + 0.25 < time.elapsed_seconds() {
+            movement_event.send(MovementAction::Swing(direction));
+
+        }
+    
+    }
+
+    if swing.swing_time + 0.5 < time.elapsed_seconds() {
+        swing.swing_number = 0;
+
+    }
+
+    // the combo is over, the animation may have ended it already
+    if swing.swing_number == 0 && *state == PlayerState::Swing {
+        change_state(player, &mut state, PlayerState::Idle, &mut state_changed);
+    }
 
-                *gun_visibility = Visibility::Hidden;
-                *wrench_visibility = Visibility::Visible; 
 
-                swing.swing_number += 1;
-                swing.swing_time = time.elapsed_seconds();
-                
-                let direction = Vec2::new(
-                    transfom.forward().x, 
-                    transfom.forward().z, 
-                );
 
-                movement_event.send(MovementAction::Swing(direction));
 
+}
+
+// fires at the grapple point closest to the center of the screen, then pulls the player
+// to it or lets it swing from it until the button is released
+fn swingshot(
+    mut player: Query<(
+        Entity,
+        &mut Grapple,
+        &mut PlayerState,
+        &Transform,
+        &mut InputBuffer,
+        &mut GroundedHeight,
+        &GravityDirection
+    ), With<CharacterController>>,
+    grapple_points: Query<(Entity, &GrapplePoint, &GlobalTransform)>,
+    camera: Query<&Transform, (Without<CharacterController>, With<CameraIdentifier>)>,
+    intent: Query<&PlayerIntent, With<CharacterController>>,
+    mut gun: Query<&mut Visibility, (With<Gun>, Without<Wrench>)>,
+    mut wrench: Query<&mut Visibility, (With<Wrench>, Without<Gun>)>,
+    mut swingshot: Query<&mut Visibility, (With<Swingshot>, Without<Gun>, Without<Wrench>)>,
+    spatial_query: SpatialQuery,
+    mut movement_event: EventWriter<MovementAction>,
+    mut state_changed: EventWriter<StateChanged>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    tuning: Res<MovementTuning>,
+) {
+    let Ok((player, mut grapple, mut state, transform, mut input_buffer, mut grounded_height, gravity_direction)) = player.get_single_mut() else {return;};
+    let Ok(intent) = intent.get_single() else {return;};
+    let Ok(camera_transform) = camera.get_single() else {return;};
+    let Ok(mut gun_visibility) = gun.get_single_mut() else {return;};
+    let Ok(mut wrench_visibility) = wrench.get_single_mut() else {return;};
+    let Ok(mut swingshot_visibility) = swingshot.get_single_mut() else {return;};
+
+    if *state != PlayerState::Swingshot {
+        // let go, or something else like landing ended the swingshot
+        if grapple.target.is_some() {
+            if let Some(joint) = grapple.joint {
+                commands.entity(joint).despawn();
             }
-        
+
+            grapple.target = None;
+            grapple.joint = None;
+            *swingshot_visibility = Visibility::Hidden;
+        }
+
+        if input_buffer.grapple_time.is_none() {return;}
+
+        // in range, in front of the camera and not behind a wall
+        let target = grapple_points.iter()
+            .filter_map(|(entity, point, point_transform)| {
+                let to_point = point_transform.translation() - transform.translation;
+                if to_point.length() > tuning.swingshot_range {return None;}
+
+                let centered = (point_transform.translation() - camera_transform.translation).normalize_or_zero().dot(*camera_transform.forward());
+                if centered < 0.85 {return None;}
+
+                let direction = Direction3d::new(to_point).ok()?;
+                let blocked = spatial_query.cast_ray(
+                    transform.translation,
+                    direction,
+                    to_point.length(),
+                    true,
+                    SpatialQueryFilter::from_excluded_entities([player])
+                ).is_some();
+
+                (!blocked).then_some((entity, point.0, to_point.length(), centered))
+            })
+            .max_by(|(_, _, _, a), (_, _, _, b)| a.total_cmp(b));
+
+        let Some((target, mode, distance, _)) = target else {return;};
+
+        if !change_state(player, &mut state, PlayerState::Swingshot, &mut state_changed) {return;}
+
+        input_buffer.grapple_time = None;
+        grapple.target = Some(target);
+
+        *gun_visibility = Visibility::Hidden;
+        *wrench_visibility = Visibility::Hidden;
+        *swingshot_visibility = Visibility::Visible;
+
+        if mode == GrappleMode::Swing {
+            // the rope starts a little shorter so it lifts the player off the ground
+            let length = distance * 0.95;
+
+            grapple.joint = Some(commands.spawn(
+                DistanceJoint::new(target, player)
+                    .with_rest_length(length)
+                    .with_limits(0., length)
+            ).id());
         }
 
-        if swing.swing_time + 0.5 < time.elapsed_seconds() {
-            swing.swing_number = 0;
+        commands.spawn((
+            AudioBundle {
+                source: asset_server.load("swingshot.ogg"),
+                settings: PlaybackSettings {
+                    volume: Volume::new(0.07),
+                    mode: PlaybackMode::Despawn,
+                    ..default()
+                }
+            },
+        ));
+        return;
+    }
 
+    let Some((_, point, point_transform)) = grapple.target.and_then(|target| grapple_points.get(target).ok()) else {
+        change_state(player, &mut state, PlayerState::Fall, &mut state_changed);
+        return;
+    };
+
+    grounded_height.0 = gravity_direction.height(transform.translation);
+
+    match point.0 {
+        GrappleMode::Pull => {
+            let to_point = point_transform.translation() - transform.translation;
+
+            // close enough, the player flies on with the speed it was pulled with
+            if to_point.length() < tuning.swingshot_arrive_distance {
+                change_state(player, &mut state, PlayerState::Fall, &mut state_changed);
+                return;
+            }
+
+            movement_event.send(MovementAction::Grapple(to_point.normalize() * tuning.swingshot_pull_speed));
         }
+        GrappleMode::Swing => {
+            if !intent.grapple_held {
+                change_state(player, &mut state, PlayerState::Fall, &mut state_changed);
+                return;
+            }
 
+            // the stick pumps the swing
+            let stick = intent.movement.clamp_length_max(1.);
+            // the walk heading is on the ground of the player's gravity, the camera is flattened onto it
+            let camera_forward = gravity_direction.heading(gravity_direction.flatten(*camera_transform.forward())).normalize_or_zero();
+            let camera_right = gravity_direction.heading(gravity_direction.flatten(*camera_transform.right())).normalize_or_zero();
 
-    }    
+            movement_event.send(MovementAction::Walk((camera_forward * stick.y + camera_right * stick.x) * tuning.swingshot_swing_control));
+        }
+    }
+}
 
+// the rope from the player to the grapple point
+fn draw_swingshot_rope(
+    player: Query<(&Grapple, &Transform), With<CharacterController>>,
+    grapple_points: Query<&GlobalTransform, With<GrapplePoint>>,
+    mut gizmos: Gizmos,
+) {
+    let Ok((grapple, transform)) = player.get_single() else {return;};
+    let Some(point_transform) = grapple.target.and_then(|target| grapple_points.get(target).ok()) else {return;};
 
-}
\ No newline at end of file
+    gizmos.line(transform.translation, point_transform.translation(), Color::GRAY);
+}